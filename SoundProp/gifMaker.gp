@@ -8,16 +8,18 @@ set rmargin at screen 0.75
 set tmargin at screen 0.92
 set bmargin at screen 0.10
 
-# Define input and output paths
-infile = "./outputdata/dataset"
-outfile = "./outputImages/imageGif.gif"
-boundary = "./outputdata/boundary"
+# Define input and output paths, given as the output directory with the file prefix and the GIF file
+if (!exists("data")) data = "./outputdata/"
+if (!exists("image")) image = "./outputImages/imageGif.gif"
+infile = data."dataset"
+outfile = image
+boundary = data."boundary"
 
 set xrange [x_min:x_max]
 set yrange [y_min:y_max]
-set cbrange [0:max_intensity/10000]
-set cbtics format "%.1e"
-set cblabel "Intensity (W/m^2)" rotate by -90 offset 2,0
+set cbrange [max_level-100:max_level-40]
+set cbtics format "%.0f"
+set cblabel "Received level (dB re 1 uPa)" rotate by -90 offset 2,0
 
 
 # Output to GIF file
@@ -1 +1,5 @@
-gnuplot -e "frames=%1; boundaries=%2; x_min=%3; x_max=%4; y_min=%5; y_max=%6; duration=%7; max_intensity=%8" gifMaker.gp
+set data=%~1
+set image=%~2
+shift
+shift
+gnuplot -e "data='%data%'; image='%image%'; frames=%1; boundaries=%2; x_min=%3; x_max=%4; y_min=%5; y_max=%6; duration=%7; max_level=%8" gifMaker.gp
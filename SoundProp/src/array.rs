@@ -0,0 +1,65 @@
+//                                                  MARK: Source Array Struct
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceArray {
+    elements: Vec<ArrayElement>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArrayElement {
+    pub offset: [f64;2],
+    pub delay: f64,
+    pub amplitude: f64,
+} // The position of an element relative to the centre of the array, with the time it is fired after the start and its pressure amplitude.
+
+impl SourceArray {
+    pub fn new(elements: Vec<[f64;4]>) -> Self {
+        if elements.is_empty() {
+            eprintln!("Error: A source array must have at least one element.");
+            std::process::exit(1);
+        }
+        if elements.iter().any(|element| element[2].is_nan() || element[2] < 0.0 || element[3].is_nan() || element[3] <= 0.0) {
+            eprintln!("Error: The delay of every element must be a positive float value, and its amplitude must be positive and non-zero.");
+            std::process::exit(1);
+        }
+
+        Self {
+            elements: elements.iter().map(|element| ArrayElement {
+                offset: [element[0], element[1]],
+                delay: element[2],
+                amplitude: element[3],
+            }).collect(),
+        }
+    } // Takes each element as [x offset, y offset, delay, amplitude].
+
+    pub fn line(number_of_elements: usize, spacing: f64, axis: f64, steering: f64, speed_of_sound: f64) -> Self {
+        if number_of_elements == 0 || spacing <= 0.0 || speed_of_sound <= 0.0 {
+            eprintln!("Error: number_of_elements, spacing and speed_of_sound must be positive, non-zero, values.");
+            std::process::exit(1);
+        }
+
+        let centre = (number_of_elements - 1) as f64 / 2.0;
+        let positions: Vec<f64> = (0..number_of_elements).map(|n| (n as f64 - centre) * spacing).collect();
+        let delays: Vec<f64> = positions.iter().map(|position| position * steering.sin() / speed_of_sound).collect();
+        let first_delay = delays.iter().cloned().fold(f64::INFINITY, f64::min);
+        // An element further along the steering direction is fired later, so every wavefront lines up in that direction.
+
+        Self::new(positions.iter().zip(&delays).map(|(position, delay)| {
+            [position * axis.cos(), position * axis.sin(), delay - first_delay, 1.0]
+        }).collect())
+    } // Evenly spaced elements across the axis (measured like the launch angles of the rays), steered by an angle from it.
+
+    pub fn shade(mut self, amplitudes: Vec<f64>) -> Self {
+        if amplitudes.len() != self.elements.len() || amplitudes.iter().any(|amplitude| amplitude.is_nan() || *amplitude <= 0.0) {
+            eprintln!("Error: There must be one positive, non-zero, amplitude for each element of the array.");
+            std::process::exit(1);
+        }
+        for (element, amplitude) in self.elements.iter_mut().zip(amplitudes) {
+            element.amplitude = amplitude;
+        }
+        self
+    } // Replaces the amplitude of each element, such as with a Hann or Chebyshev window to lower the side lobes.
+
+    pub fn elements(&self) -> &[ArrayElement] {
+        &self.elements
+    }
+}
@@ -0,0 +1,87 @@
+use std::f64::consts::{FRAC_2_PI, FRAC_PI_4, PI};
+
+//                                                  MARK: Beam Pattern Enum
+#[derive(Clone, Debug, PartialEq)]
+pub enum BeamPattern {
+    Omnidirectional,
+    Piston { radius: f64 },
+    LineArray { spacing: f64, steering: f64, shading: Vec<f64> },
+    Cardioid,
+    Table(Vec<[f64;2]>),
+} // The directivity of a source, which is 1.0 along its axis.
+
+impl BeamPattern {
+    pub fn line_array(number_of_elements: usize, spacing: f64, steering: f64) -> Self {
+        Self::LineArray { spacing, steering, shading: vec![1.0; number_of_elements] }
+    } // An unshaded line array. Shading can be given by building BeamPattern::LineArray directly, with one weight per element.
+
+    pub fn check(&self) {
+        let valid = match self {
+            Self::Omnidirectional | Self::Cardioid => true,
+            Self::Piston { radius } => *radius > 0.0,
+            Self::LineArray { spacing, steering, shading } => *spacing > 0.0 && steering.abs() < PI / 2.0
+                && !shading.is_empty() && shading.iter().sum::<f64>() != 0.0,
+            Self::Table(points) => !points.is_empty() && points.windows(2).all(|pair| pair[0][0] < pair[1][0]),
+        };
+        if !valid {
+            eprintln!("Error: A piston must have a positive radius, a line array must have a positive spacing, a steering angle within +/- π/2 and at least one element, and a table must have angles in ascending order.");
+            std::process::exit(1);
+        }
+    }
+
+    pub fn response(&self, off_axis: f64, wavenumber: f64) -> (f64, f64) {
+        let amplitude: [f64;2] = match self {
+            Self::Omnidirectional => [1.0, 0.0],
+            Self::Piston { radius } => {
+                if off_axis.abs() > PI / 2.0 { [0.0, 0.0] }
+                else {
+                    let argument = wavenumber * radius * off_axis.sin();
+                    if argument.abs() < 1e-8 { [1.0, 0.0] } else { [2.0 * bessel_j1(argument) / argument, 0.0] }
+                }
+            } // A circular piston in a rigid baffle, which does not radiate behind itself.
+            Self::LineArray { spacing, steering, shading } => {
+                let centre = (shading.len() - 1) as f64 / 2.0;
+                let phase_step = wavenumber * spacing * (off_axis.sin() - steering.sin());
+                let mut sum = [0.0, 0.0];
+                for (n, weight) in shading.iter().enumerate() {
+                    let phase = (n as f64 - centre) * phase_step;
+                    sum[0] += weight * phase.cos();
+                    sum[1] += weight * phase.sin();
+                }
+                let total: f64 = shading.iter().sum();
+                [sum[0] / total, sum[1] / total]
+            } // Elements are evenly spaced across the axis, centred on the source, and delayed to steer the main lobe.
+            Self::Cardioid => [(1.0 + off_axis.cos()) / 2.0, 0.0],
+            Self::Table(points) => {
+                let level = if off_axis <= points[0][0] { points[0][1] }
+                    else if off_axis >= points[points.len() - 1][0] { points[points.len() - 1][1] }
+                    else {
+                        let k = points.windows(2).position(|pair| off_axis < pair[1][0]).unwrap();
+                        points[k][1] + (off_axis - points[k][0]) / (points[k + 1][0] - points[k][0]) * (points[k + 1][1] - points[k][1])
+                    };
+                [10.0_f64.powf(level / 20.0), 0.0]
+            } // Takes [off-axis angle, level in dB] points, with linear interpolation between them.
+        };
+
+        let phase = if amplitude[1] == 0.0 && amplitude[0] >= 0.0 { 0.0 } else { amplitude[1].atan2(amplitude[0]) };
+        (amplitude[0].powi(2) + amplitude[1].powi(2), phase)
+    } // Returns the fraction of the on-axis intensity radiated at an angle from the axis, and the phase shift of the pressure there.
+}
+
+fn bessel_j1(x: f64) -> f64 {
+    let ax = x.abs();
+    if ax < 8.0 {
+        let y = x * x;
+        let numerator = x * (72362614232.0 + y * (-7895059235.0 + y * (242396853.1 + y * (-2972611.439 + y * (15704.48260 + y * (-30.16036606))))));
+        let denominator = 144725228442.0 + y * (2300535178.0 + y * (18583304.74 + y * (99447.43394 + y * (376.9991397 + y))));
+        numerator / denominator
+    } else {
+        let z = 8.0 / ax;
+        let y = z * z;
+        let xx = ax - 3.0 * FRAC_PI_4;
+        let p = 1.0 + y * (0.183105e-2 + y * (-0.3516396496e-4 + y * (0.2457520174e-5 + y * (-0.240337019e-6))));
+        let q = 0.04687499995 + y * (-0.2002690873e-3 + y * (0.8449199096e-5 + y * (-0.88228987e-6 + y * 0.105787412e-6)));
+        let value = (FRAC_2_PI / ax).sqrt() * (xx.cos() * p - z * xx.sin() * q);
+        if x < 0.0 { -value } else { value }
+    }
+} // Rational and asymptotic approximations to the Bessel function of the first kind, order one.
@@ -0,0 +1,113 @@
+use crate::random::Random;
+
+//                                                  MARK: Branch Control Struct
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BranchControl {
+    max_depth: Option<usize>,
+    threshold: f64,
+    roulette: Option<(f64, f64)>,
+    budget: Option<usize>,
+}
+
+impl BranchControl {
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = Some(depth);
+    } // Rays which have already split this many times no longer create reflected rays.
+
+    pub fn set_threshold(&mut self, threshold: f64) {
+        self.threshold = threshold;
+    } // Reflected rays weaker than this fraction of the strongest source are not created.
+
+    pub fn set_roulette(&mut self, threshold: f64, survival: f64) {
+        self.roulette = Some((threshold, survival));
+    } // Reflected rays weaker than the threshold only survive with the given probability.
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = Some(budget);
+    } // The most rays which are kept at the end of each step.
+
+    pub fn branch(&self, depth: usize, intensity: f64, max_intensity: f64, state: &[f64]) -> Option<f64> {
+        if self.max_depth.is_some_and(|max_depth| depth >= max_depth) { return None }
+        if intensity < self.threshold * max_intensity { return None }
+
+        match self.roulette {
+            Some((threshold, survival)) if intensity < threshold * max_intensity => {
+                (draw(state) < survival).then_some(1.0 / survival)
+            } // The rays which survive carry the energy of those which do not, so none is lost on average.
+            _ => Some(1.0),
+        }
+    } // Decides whether a ray which splits should create a branch of the given depth and intensity. Returns the factor to scale the branch by.
+
+    pub fn keep_probabilities(&self, intensities: &[f64]) -> Option<Vec<f64>> {
+        let budget = self.budget.filter(|budget| intensities.len() > *budget)?;
+
+        let mut sorted: Vec<f64> = intensities.iter().copied().filter(|intensity| *intensity > 0.0).collect();
+        if sorted.len() <= budget {
+            let spare = (budget - sorted.len()) as f64 / (intensities.len() - sorted.len()) as f64;
+            return Some(intensities.iter().map(|intensity| if *intensity > 0.0 { 1.0 } else { spare }).collect());
+        } // Every ray with energy fits, and the rest of the budget is shared evenly between the rays without, so a wavefront with no energy left is not dropped entirely.
+
+        sorted.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        let mut tails = sorted.clone();
+        for i in (0..tails.len() - 1).rev() {
+            tails[i] += tails[i + 1];
+        } // The energy of each ray and every weaker one, summed from the weakest up, so it is never less than the ray's own.
+        let mut scale = 0.0;
+        for (kept, (intensity, tail)) in sorted.iter().zip(&tails).enumerate() {
+            scale = (budget - kept) as f64 / tail;
+            if scale * intensity <= 1.0 { break }
+        } // Finds the scale where the probabilities, capped at one, add up to the budget. The strongest rays are always kept.
+
+        Some(intensities.iter().map(|intensity| if *intensity > 0.0 { (scale * intensity).min(1.0) } else { 0.0 }).collect())
+    } // Returns the probability of keeping each ray, in proportion to its intensity, when there are more rays than the budget. Rays with no energy left are dropped first.
+}
+
+pub fn draw(state: &[f64]) -> f64 {
+    let seed = state.iter().fold(0, |seed, value| Random::seed(seed ^ value.to_bits()).next_u64());
+    Random::seed(seed).uniform()
+} // A random number in the range [0, 1) which depends only on the state given, so runs give the same result on any number of threads.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budgeted(budget: usize) -> BranchControl {
+        let mut control = BranchControl::default();
+        control.set_budget(budget);
+        control
+    }
+
+    #[test]
+    fn expected_number_kept_is_the_budget() {
+        let mut intensities: Vec<f64> = (1..=200).map(|i| (i as f64).powi(3)).collect();
+        intensities.extend([0.0, 0.0, -1.0]);
+        for budget in [0, 1, 10, 50, 150, 200] {
+            let probabilities = budgeted(budget).keep_probabilities(&intensities).unwrap();
+            assert!((probabilities.iter().sum::<f64>() - budget as f64).abs() < 1e-9 * budget.max(1) as f64, "budget {}", budget);
+            assert!(probabilities.iter().all(|probability| (0.0..=1.0).contains(probability)), "budget {}", budget);
+            assert!(probabilities.windows(2).take(199).all(|pair| pair[0] <= pair[1]), "budget {}", budget);
+            assert_eq!(&probabilities[200..], &[0.0; 3], "budget {}", budget);
+        }
+    } // Stronger rays are more likely to be kept, and rays with no energy are dropped.
+
+    #[test]
+    fn strong_rays_are_always_kept() {
+        let mut intensities = vec![1.0; 99];
+        intensities.push(1000.0);
+        let probabilities = budgeted(10).keep_probabilities(&intensities).unwrap();
+        assert_eq!(probabilities[99], 1.0);
+        assert!(probabilities[..99].iter().all(|probability| (probability - 9.0 / 99.0).abs() < 1e-12));
+    } // The weaker rays share what is left of the budget.
+
+    #[test]
+    fn rays_within_the_budget_are_all_kept() {
+        assert_eq!(budgeted(3).keep_probabilities(&[1.0, 2.0, 3.0]), None);
+        assert_eq!(budgeted(3).keep_probabilities(&[1.0, 0.0, 1e-300, 3.0]), Some(vec![1.0, 0.0, 1.0, 1.0]));
+    } // Including when only as many rays as the budget have energy.
+
+    #[test]
+    fn rays_with_no_energy_share_the_budget() {
+        assert_eq!(budgeted(4).keep_probabilities(&[0.0; 10]), Some(vec![0.4; 10]));
+        assert_eq!(budgeted(4).keep_probabilities(&[0.0, 5.0, 0.0, 0.0, 0.0]), Some(vec![0.75, 1.0, 0.75, 0.75, 0.75]));
+    }
+}
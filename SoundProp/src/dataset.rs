@@ -0,0 +1,244 @@
+use crate::{material::MaterialType, paths::number};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+//                                                  MARK: Dataset Format Enum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DatasetFormat {
+    Text,
+    Csv,
+    JsonLines,
+}
+
+impl DatasetFormat {
+    pub fn filename(&self, frame: usize) -> String {
+        match self {
+            Self::Text => format!("dataset{}.txt", frame),
+            Self::Csv => format!("dataset{}.csv", frame),
+            Self::JsonLines => "datasets.jsonl".to_string(),
+        }
+    } // JSON Lines keeps every frame in one file, one line each.
+}
+
+//                                                  MARK: Frame Metadata Struct
+#[derive(Clone, Debug)]
+pub struct SourceSummary {
+    pub source_type: &'static str,
+    pub location: [f64;2],
+    pub level: f64,
+    pub number_of_rays: usize,
+} // Where a source starts (m, y upwards), its level (dB re 1 µPa @ 1 m) and the rays it launches.
+
+#[derive(Clone, Debug)]
+pub struct FrameMetadata {
+    pub frame: usize,
+    pub time: f64,
+    pub square_size: f64,
+    pub x_range: [f64;2],
+    pub y_range: [f64;2],
+    pub sources: Vec<SourceSummary>,
+    pub boundaries: Vec<MaterialType>,
+} // Describes one frame of the received level (s, m), with the sources and the material of each boundary in the order they were added.
+
+pub fn encode(format: DatasetFormat, metadata: &FrameMetadata, x_positions: &[f64], y_positions: &[f64], levels: &[f64]) -> String {
+    match format {
+        DatasetFormat::Text => {
+            let mut output = String::new();
+            for i in 0..x_positions.len() { output.push_str(&format!("{} {} {}\n", x_positions[i], y_positions[i], levels[i])) }
+            output
+        } // Bare 'x y level' lines, as read by gifMaker.gp.
+        DatasetFormat::Csv => {
+            let mut output = format!("# SoundProp {}\n# frame: {}\n# time: {} s\n# grid: square size {} m, x from {} to {} m, y from {} to {} m\n",
+                VERSION, metadata.frame, metadata.time, metadata.square_size, metadata.x_range[0], metadata.x_range[1], metadata.y_range[0], metadata.y_range[1]);
+            for (s, source) in metadata.sources.iter().enumerate() {
+                output.push_str(&format!("# source {}: {} at ({}, {}) m, {} dB re 1 uPa @ 1 m, {} rays\n",
+                    s, source.source_type, source.location[0], source.location[1], source.level, source.number_of_rays));
+            }
+            for (b, material) in metadata.boundaries.iter().enumerate() {
+                output.push_str(&format!("# boundary {}: {:?}\n", b, material));
+            }
+            output.push_str("# columns: x (m), y (m, upwards) and level (dB re 1 uPa) at the centre of each grid square reached\nx,y,level\n");
+            for i in 0..x_positions.len() { output.push_str(&format!("{},{},{}\n", x_positions[i], y_positions[i], levels[i])) }
+            output
+        } // The metadata is given in comment lines starting with '#', ahead of the column names.
+        DatasetFormat::JsonLines => {
+            let sources: Vec<String> = metadata.sources.iter().map(|source| format!("{{\"type\":\"{}\",\"x\":{},\"y\":{},\"level\":{},\"rays\":{}}}",
+                source.source_type, number(source.location[0]), number(source.location[1]), number(source.level), source.number_of_rays)).collect();
+            let boundaries: Vec<String> = metadata.boundaries.iter().map(|material| format!("\"{:?}\"", material)).collect();
+            let data: Vec<String> = (0..x_positions.len()).map(|i| format!("[{},{},{}]", number(x_positions[i]), number(y_positions[i]), number(levels[i]))).collect();
+
+            format!("{{\"version\":\"{}\",\"frame\":{},\"time\":{},\"grid\":{{\"square_size\":{},\"x_range\":[{},{}],\"y_range\":[{},{}]}},\"sources\":[{}],\"boundaries\":[{}],\
+                \"units\":{{\"time\":\"s\",\"x\":\"m\",\"y\":\"m\",\"level\":\"dB re 1 uPa\"}},\"columns\":[\"x\",\"y\",\"level\"],\"data\":[{}]}}\n",
+                VERSION, metadata.frame, number(metadata.time), number(metadata.square_size), number(metadata.x_range[0]), number(metadata.x_range[1]),
+                number(metadata.y_range[0]), number(metadata.y_range[1]), sources.join(","), boundaries.join(","), data.join(","))
+        } // One JSON object holding the metadata and every [x, y, level] point of the frame.
+    }
+} // Returns one frame of the received level as the contents of a file, or of one line for JSON Lines.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{iter::Peekable, str::Chars};
+
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Null,
+        Number(f64),
+        Text(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(fields) => &fields.iter().find(|(name, _)| name == key).unwrap_or_else(|| panic!("No key {}", key)).1,
+                _ => panic!("{:?} is not an object", self),
+            }
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            match self {
+                Json::Object(fields) => fields.iter().map(|(name, _)| name.as_str()).collect(),
+                _ => panic!("{:?} is not an object", self),
+            }
+        }
+
+        fn items(&self) -> &[Json] {
+            match self {
+                Json::Array(items) => items,
+                _ => panic!("{:?} is not an array", self),
+            }
+        }
+    }
+
+    fn parse(line: &str) -> Json {
+        let mut chars = line.chars().peekable();
+        let value = parse_value(&mut chars);
+        assert_eq!(chars.collect::<String>(), "\n");
+        value
+    } // Reads a line written by encode, which has no whitespace and no escaped characters in its strings.
+
+    fn parse_value(chars: &mut Peekable<Chars>) -> Json {
+        match chars.next() {
+            Some('{') => {
+                let mut fields = Vec::new();
+                if chars.next_if_eq(&'}').is_some() { return Json::Object(fields) }
+                loop {
+                    let key = match parse_value(chars) {
+                        Json::Text(key) => key,
+                        value => panic!("{:?} is not a key", value),
+                    };
+                    assert_eq!(chars.next(), Some(':'));
+                    fields.push((key, parse_value(chars)));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Json::Object(fields),
+                        other => panic!("Expected ',' or '}}', found {:?}", other),
+                    }
+                }
+            }
+            Some('[') => {
+                let mut items = Vec::new();
+                if chars.next_if_eq(&']').is_some() { return Json::Array(items) }
+                loop {
+                    items.push(parse_value(chars));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Json::Array(items),
+                        other => panic!("Expected ',' or ']', found {:?}", other),
+                    }
+                }
+            }
+            Some('"') => Json::Text(chars.by_ref().take_while(|c| *c != '"').collect()),
+            Some('n') => {
+                assert_eq!(chars.by_ref().take(3).collect::<String>(), "ull");
+                Json::Null
+            }
+            Some(first) => {
+                let mut number = first.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) { number.push(c) }
+                Json::Number(number.parse().unwrap_or_else(|_| panic!("{} is not a number", number)))
+            }
+            None => panic!("The line ended early"),
+        }
+    }
+
+    fn metadata() -> FrameMetadata {
+        FrameMetadata {
+            frame: 3,
+            time: 0.25,
+            square_size: 5.0,
+            x_range: [-100.0, 100.0],
+            y_range: [-50.0, 0.0],
+            sources: vec![
+                SourceSummary { source_type: "Point", location: [0.0, -10.0], level: 180.0, number_of_rays: 1000 },
+                SourceSummary { source_type: "Line", location: [20.5, -30.0], level: 175.5, number_of_rays: 250 },
+            ],
+            boundaries: vec![MaterialType::Sand, MaterialType::Basalt],
+        }
+    }
+
+    const X: [f64; 3] = [-2.5, 2.5, 97.5];
+    const Y: [f64; 3] = [-2.5, -7.5, -47.5];
+    const LEVELS: [f64; 3] = [120.25, f64::NAN, f64::NEG_INFINITY];
+    // Squares with no energy, or a level which is not a number, are written as well.
+
+    #[test]
+    fn json_lines_hold_the_metadata_and_every_point() {
+        let line = encode(DatasetFormat::JsonLines, &metadata(), &X, &Y, &LEVELS);
+        let frame = parse(&line);
+
+        assert_eq!(frame.keys(), ["version", "frame", "time", "grid", "sources", "boundaries", "units", "columns", "data"]);
+        assert_eq!(frame.get("version"), &Json::Text(VERSION.to_string()));
+        assert_eq!(frame.get("frame"), &Json::Number(3.0));
+        assert_eq!(frame.get("time"), &Json::Number(0.25));
+        assert_eq!(frame.get("grid").get("square_size"), &Json::Number(5.0));
+        assert_eq!(frame.get("grid").get("x_range").items(), [Json::Number(-100.0), Json::Number(100.0)]);
+        assert_eq!(frame.get("grid").get("y_range").items(), [Json::Number(-50.0), Json::Number(0.0)]);
+
+        let sources = frame.get("sources").items();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[1].keys(), ["type", "x", "y", "level", "rays"]);
+        assert_eq!(sources[1].get("type"), &Json::Text("Line".to_string()));
+        assert_eq!(sources[1].get("x"), &Json::Number(20.5));
+        assert_eq!(sources[1].get("level"), &Json::Number(175.5));
+        assert_eq!(sources[1].get("rays"), &Json::Number(250.0));
+        assert_eq!(frame.get("boundaries").items(), [Json::Text("Sand".to_string()), Json::Text("Basalt".to_string())]);
+        assert_eq!(frame.get("units").get("level"), &Json::Text("dB re 1 uPa".to_string()));
+        assert_eq!(frame.get("columns").items(), ["x", "y", "level"].map(|column| Json::Text(column.to_string())));
+
+        let data = frame.get("data").items();
+        assert_eq!(data.len(), X.len());
+        assert_eq!(data[0].items(), [Json::Number(-2.5), Json::Number(-2.5), Json::Number(120.25)]);
+        assert_eq!(data[1].items(), [Json::Number(2.5), Json::Number(-7.5), Json::Null]);
+        assert_eq!(data[2].items(), [Json::Number(97.5), Json::Number(-47.5), Json::Null]);
+    } // Values which are not finite are written as null, as JSON has no way of writing them.
+
+    #[test]
+    fn json_lines_frames_without_points_are_valid() {
+        let line = encode(DatasetFormat::JsonLines, &FrameMetadata { sources: Vec::new(), boundaries: Vec::new(), ..metadata() }, &[], &[], &[]);
+        let frame = parse(&line);
+        assert!(frame.get("sources").items().is_empty());
+        assert!(frame.get("boundaries").items().is_empty());
+        assert!(frame.get("data").items().is_empty());
+    }
+
+    #[test]
+    fn csv_columns_follow_the_comments() {
+        let file = encode(DatasetFormat::Csv, &metadata(), &X, &Y, &LEVELS);
+        let comments: Vec<&str> = file.lines().take_while(|line| line.starts_with('#')).collect();
+        let rows: Vec<&str> = file.lines().skip(comments.len()).collect();
+
+        assert_eq!(comments[0], format!("# SoundProp {}", VERSION));
+        assert!(comments.contains(&"# source 1: Line at (20.5, -30) m, 175.5 dB re 1 uPa @ 1 m, 250 rays"));
+        assert!(comments.contains(&"# boundary 1: Basalt"));
+        assert_eq!(rows[0], "x,y,level");
+        assert_eq!(rows[1..], ["-2.5,-2.5,120.25", "2.5,-7.5,NaN", "97.5,-47.5,-inf"]);
+    }
+
+    #[test]
+    fn text_has_one_line_per_point() {
+        assert_eq!(encode(DatasetFormat::Text, &metadata(), &X, &Y, &LEVELS), "-2.5 -2.5 120.25\n2.5 -7.5 NaN\n97.5 -47.5 -inf\n");
+    } // No header, as gifMaker.gp reads the file directly.
+}
@@ -0,0 +1,52 @@
+//                                                  MARK: Ensemble Statistics Struct
+pub struct EnsembleStatistics {
+    pub x_positions: Vec<f64>,
+    pub y_positions: Vec<f64>,
+    pub mean: Vec<f64>,
+    pub variance: Vec<f64>,
+    pub coverage: Vec<f64>,
+    pub percentiles: Vec<(f64, Vec<f64>)>,
+} // Per grid square transmission loss statistics (dB) across every member of an ensemble.
+
+impl EnsembleStatistics {
+    pub fn calculate(cells: Vec<([f64;2], Vec<f64>)>, percentiles: &[f64]) -> Self {
+        let mut statistics = Self {
+            x_positions: Vec::with_capacity(cells.len()),
+            y_positions: Vec::with_capacity(cells.len()),
+            mean: Vec::with_capacity(cells.len()),
+            variance: Vec::with_capacity(cells.len()),
+            coverage: Vec::with_capacity(cells.len()),
+            percentiles: percentiles.iter().map(|percentile| (*percentile, Vec::with_capacity(cells.len()))).collect(),
+        };
+
+        for (centre, mut transmission_loss) in cells {
+            let reached: Vec<f64> = transmission_loss.iter().copied().filter(|value| value.is_finite()).collect();
+            let mean = reached.iter().sum::<f64>() / reached.len() as f64;
+            let variance = if reached.len() > 1 {
+                reached.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (reached.len() - 1) as f64
+            } else { 0.0 };
+            // The mean and variance only include the members in which a ray reached the grid square.
+
+            transmission_loss.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for (percentile, values) in statistics.percentiles.iter_mut() {
+                values.push(percentile_of(&transmission_loss, *percentile));
+            } // Members which no ray reached count as an infinite loss for the percentiles.
+
+            statistics.x_positions.push(centre[0]);
+            statistics.y_positions.push(centre[1]);
+            statistics.mean.push(mean);
+            statistics.variance.push(variance);
+            statistics.coverage.push(reached.len() as f64 / transmission_loss.len() as f64);
+        }
+        statistics
+    } // Takes the centre of each grid square with its transmission loss in every member (infinite if it was not reached).
+}
+
+fn percentile_of(sorted_values: &[f64], percentile: f64) -> f64 {
+    let position = percentile / 100.0 * (sorted_values.len() - 1) as f64;
+    let lower = sorted_values[position.floor() as usize];
+    let upper = sorted_values[position.ceil() as usize];
+
+    if upper.is_infinite() { return upper }
+    lower + (position - position.floor()) * (upper - lower)
+} // Linearly interpolates between the closest ranks of the sorted values.
@@ -0,0 +1,66 @@
+use crate::material::MaterialType;
+
+//                                                  MARK: Interaction Enums
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interaction {
+    Reflection,
+    Transmission,
+    TotalInternalReflection,
+    Termination(Termination),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Termination {
+    LeftSimulation,
+    NotFinite,
+    FadedOut,
+    Resampled,
+} // Why a ray was removed from the simulation.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interface {
+    Boundary(usize),
+    Surface,
+} // The interface a ray interacted with, where boundaries are numbered in the order they were added.
+
+//                                                  MARK: Event Struct
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    pub ray: usize,
+    pub time: f64,
+    pub position: [f64;2],
+    pub interaction: Interaction,
+    pub interface: Option<Interface>,
+    pub material: Option<MaterialType>,
+    pub incidence: Option<f64>,
+    pub coefficients: Option<[f64;3]>,
+} // One interaction of a ray (m, y upwards), with the material of the boundary or ice at the interface (None for air),
+// the angle of incidence from the normal (rad) and the fractions of energy reflected and transmitted, and the reflected phase shift (rad).
+
+pub fn encode(events: &[Event]) -> String {
+    let mut output = String::from("ray,time,x,y,interaction,reason,interface,material,incidence,reflected,transmitted,phase\n");
+    for event in events {
+        let (interaction, reason) = match event.interaction {
+            Interaction::Reflection => ("reflection", String::new()),
+            Interaction::Transmission => ("transmission", String::new()),
+            Interaction::TotalInternalReflection => ("total_internal_reflection", String::new()),
+            Interaction::Termination(reason) => ("termination", format!("{:?}", reason)),
+        };
+        let interface = match event.interface {
+            Some(Interface::Boundary(boundary)) => boundary.to_string(),
+            Some(Interface::Surface) => "surface".to_string(),
+            None => String::new(),
+        };
+        let material = match (event.material, event.interface) {
+            (Some(material), _) => format!("{:?}", material),
+            (None, Some(Interface::Surface)) => "Air".to_string(),
+            (None, _) => String::new(),
+        };
+        let incidence = event.incidence.map_or(String::new(), |incidence| incidence.to_string());
+        let coefficients = event.coefficients.map_or(",,".to_string(), |[reflected, transmitted, phase]| format!("{},{},{}", reflected, transmitted, phase));
+
+        output.push_str(&format!("{},{},{},{},{},{},{},{},{},{}\n", event.ray, event.time, event.position[0], event.position[1],
+            interaction, reason, interface, material, incidence, coefficients));
+    }
+    output
+} // Returns the events as the contents of a CSV file, leaving the columns which do not apply to an event empty.
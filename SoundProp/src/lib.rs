@@ -0,0 +1,20 @@
+pub mod array;
+pub mod beam;
+pub mod branching;
+pub mod dataset;
+pub mod ensemble;
+pub mod events;
+pub mod material;
+pub mod ocean;
+pub mod output;
+pub mod parallel;
+pub mod paths;
+pub mod random;
+pub mod ray_trace;
+pub mod receiver;
+pub mod spectrum;
+pub mod surface;
+pub mod trajectory;
+pub mod wav;
+pub mod waveform;
+// Inputs our modules to this file.
@@ -15,6 +15,10 @@ pub enum MaterialType {
     SiliceousSediment,
     CalcerousSediment,
     Sand,
+    // Ice
+    SeaIce,
+    // User defined
+    Custom,
 }
 
 // Add a function in rays for impedence that takes in type Option<Material>
@@ -114,9 +118,39 @@ impl Material {
                     density: Density::Variable, 
                 }
             }
+            MaterialType::SeaIce => {
+                Self{
+                    material_name: material,
+                    shear_modulus: Some(9.0*1000000000.0/(2.0*(1.0+0.33))),
+                    bulk_modulus: Some(9.0*1000000000.0/(3.0*(1.0-2.0*0.33))),
+                    density: Density::Constant(910.0),
+                }
+            }
+            MaterialType::Custom => {
+                eprintln!("Error: Custom materials must be defined using Material::custom.");
+                std::process::exit(1);
+            }
         }
     }
 
+    pub fn custom(shear_modulus: f64, bulk_modulus: f64, density: f64) -> Self {
+        if shear_modulus < 0.0 || bulk_modulus <= 0.0 || density <= 0.0 {
+            eprintln!("Error: A custom material must have a positive bulk_modulus and density, and a shear_modulus of at least 0.0.");
+            std::process::exit(1);
+        }
+
+        Self{
+            material_name: MaterialType::Custom,
+            shear_modulus: Some(shear_modulus),
+            bulk_modulus: Some(bulk_modulus),
+            density: Density::Constant(density),
+        }
+    } // Defines an elastic material from its moduli (Pa) and density (kg/m^3).
+
+    pub fn material_type(&self) -> MaterialType {
+        self.material_name
+    }
+
     pub fn calculate_velocity(&mut self, depth: f64) -> f64 {
         match self.material_name {
             // Stone Materials
@@ -128,7 +162,9 @@ impl Material {
             MaterialType::Marble |
             MaterialType::Limestone |
             MaterialType::Shale |
-            MaterialType::Sandstone => ((self.bulk_modulus.unwrap() + (1.333333333333 * self.shear_modulus.unwrap())) / self.calculate_density(0.0, 0.0)).sqrt(),
+            MaterialType::Sandstone |
+            MaterialType::SeaIce |
+            MaterialType::Custom => ((self.bulk_modulus.unwrap() + (1.333333333333 * self.shear_modulus.unwrap())) / self.calculate_density(0.0, 0.0)).sqrt(),
             // Sediment Materials
             MaterialType::TurbiditeArea => (1.511+ 1.304*depth*0.001 - 0.257*(depth*0.001).powi(3))*1000.0,
             MaterialType::SiliceousSediment => (1.509 + 0.869*depth*0.001 - 0.267*(depth*0.001).powi(2))*1000.0,
@@ -137,11 +173,18 @@ impl Material {
         }
     }
 
+    pub fn shear_velocity(&mut self) -> f64 {
+        match self.shear_modulus {
+            Some(shear_modulus) => (shear_modulus / self.calculate_density(0.0, 0.0)).sqrt(),
+            None => 0.0,
+        }
+    } // Fluid-like materials without a shear modulus do not support shear waves.
+
     pub fn acoustic_impedance(&mut self, speed_of_sound: f64, depth: f64, boundary_height: f64) -> f64 {
         self.calculate_density(depth, boundary_height) * speed_of_sound
     }
 
-    fn calculate_density(&mut self, depth: f64, boundary_height: f64) -> f64 {
+    pub fn calculate_density(&mut self, depth: f64, boundary_height: f64) -> f64 {
         match self.density {
             Density::Constant(value) => value,
             Density::Variable => {
@@ -0,0 +1,330 @@
+use std::{f64::consts::PI, sync::Arc};
+use crate::random::Random;
+
+const GRAVITY: f64 = 9.81; // m s^-2
+const ATMOSPHERIC_PRESSURE: f64 = 101325.0; // Pa
+const WATER_DENSITY: f64 = 1025.0; // kg m^-3
+const INERTIAL_FREQUENCY: f64 = 9.946e-5; // rad s^-1, 2 * Omega * sin(43 degrees)
+
+//                                                  MARK: Water Column Struct
+#[derive(Clone, Default)]
+pub struct WaterColumn {
+    bubbles: Option<BubbleLayer>,
+    current: Option<Arc<dyn CurrentField>>,
+    internal_waves: Option<InternalWaves>,
+} // Optional perturbations to the water between the sea surface and the boundaries.
+
+impl WaterColumn {
+    pub fn set_bubble_layer(&mut self, bubbles: BubbleLayer) {
+        self.bubbles = Some(bubbles);
+    }
+
+    pub fn set_current(&mut self, current: Arc<dyn CurrentField>) {
+        self.current = Some(current);
+    }
+
+    pub fn set_internal_waves(&mut self, internal_waves: InternalWaves) {
+        self.internal_waves = Some(internal_waves);
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        if let Some(internal_waves) = self.internal_waves.as_mut() {
+            internal_waves.reseed(seed);
+        }
+    } // Draws new realisations of any random perturbations to the water.
+
+    pub fn current_velocity(&self, x: f64, depth: f64) -> f64 {
+        match &self.current {
+            Some(current) => current.velocity(x, depth),
+            None => 0.0,
+        }
+    } // Horizontal velocity of the water (m/s, positive in the +x direction), which is still unless a current has been set.
+
+    pub fn sound_speed(&self, background_speed: f64, x: f64, depth: f64) -> f64 {
+        let mut speed = match &self.bubbles {
+            Some(bubbles) => bubbles.sound_speed(background_speed, depth),
+            None => background_speed,
+        };
+        if let Some(internal_waves) = &self.internal_waves {
+            speed += internal_waves.speed_perturbation(background_speed, x, depth);
+        }
+        speed
+    } // Applies the perturbations to the background speed of sound at a position below the sea surface.
+
+    pub fn excess_loss(&self, frequency: f64, speed: f64, depth: f64, distance: f64) -> f64 {
+        match &self.bubbles {
+            Some(bubbles) => 10.0_f64.powf(-bubbles.attenuation(frequency, speed, depth) * distance / 10.0),
+            None => 1.0,
+        }
+    } // Returns the fraction of energy left after travelling a distance, on top of the usual seawater absorption.
+}
+
+//                                                  MARK: Bubble Layer Struct
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BubbleLayer {
+    void_fraction: f64,
+    e_folding_depth: f64,
+    bubble_radius: f64,
+}
+
+impl BubbleLayer {
+    pub fn new(void_fraction: f64, e_folding_depth: f64, bubble_radius: f64) -> Self {
+        if !(0.0..1.0).contains(&void_fraction) {
+            eprintln!("Error: void_fraction must be in the range 0.0 to 1.0.");
+            std::process::exit(1);
+        }
+        if e_folding_depth <= 0.0 || bubble_radius <= 0.0 {
+            eprintln!("Error: e_folding_depth and bubble_radius must be positive, non-zero, float values.");
+            std::process::exit(1);
+        }
+
+        Self {
+            void_fraction,
+            e_folding_depth,
+            bubble_radius,
+        }
+    } // Initialisation function for a bubble layer with a void fraction at the surface, decaying exponentially with depth.
+
+    pub fn from_wind_speed(wind_speed: f64) -> Self {
+        if wind_speed < 0.0 {
+            eprintln!("Error: wind_speed must be a positive float value.");
+            std::process::exit(1);
+        }
+        let e_folding_depth = if wind_speed <= 7.5 { 0.4 } else { 0.4 + 0.115 * (wind_speed - 7.5) };
+        // Bubbles are mixed deeper as the wind speed increases (Hall-Novarini).
+        let void_fraction = 1.0e-6 * (wind_speed / 10.0).powi(3);
+        // Bubble production follows the whitecap coverage, increasing with the cube of the wind speed.
+
+        Self::new(void_fraction, e_folding_depth, 50.0e-6)
+    } // Parameterises the bubble layer from the wind speed at 10 m (m/s), with a dominant bubble radius of 50 um.
+
+    pub fn void_fraction(&self, depth: f64) -> f64 {
+        self.void_fraction * (-depth.max(0.0) / self.e_folding_depth).exp()
+    }
+
+    fn resonance_frequency(&self, depth: f64) -> f64 {
+        3.25 / self.bubble_radius * (1.0 + 0.1 * depth.max(0.0)).sqrt()
+    } // Minnaert resonance frequency (Hz), increasing with the hydrostatic pressure.
+
+    pub fn sound_speed(&self, water_speed: f64, depth: f64) -> f64 {
+        let void_fraction = self.void_fraction(depth);
+        let pressure = ATMOSPHERIC_PRESSURE + WATER_DENSITY * GRAVITY * depth.max(0.0);
+        let mixture_density = (1.0 - void_fraction) * WATER_DENSITY;
+        let mixture_compressibility = void_fraction / (1.4 * pressure) + (1.0 - void_fraction) / (WATER_DENSITY * water_speed.powi(2));
+
+        1.0 / (mixture_density * mixture_compressibility).sqrt()
+    } // Wood's equation for the low frequency speed of sound in bubbly water. It is independent of frequency so that the ray paths are too.
+
+    pub fn attenuation(&self, frequency: f64, water_speed: f64, depth: f64) -> f64 {
+        let void_fraction = self.void_fraction(depth);
+        if void_fraction == 0.0 { return 0.0 }
+
+        let damping = 0.1;
+        let number_density = void_fraction / (4.0 / 3.0 * PI * self.bubble_radius.powi(3));
+        let ka = 2.0 * PI * frequency / water_speed * self.bubble_radius;
+        let extinction_cross_section = 4.0 * PI * self.bubble_radius.powi(2) * (damping / ka)
+            / (((self.resonance_frequency(depth) / frequency).powi(2) - 1.0).powi(2) + damping.powi(2));
+
+        4.343 * number_density * extinction_cross_section
+    } // Attenuation (dB/m) from scattering and absorption by bubbles of the dominant radius.
+}
+
+//                                                  MARK: Currents
+
+pub trait CurrentField: Send + Sync {
+    fn velocity(&self, x: f64, depth: f64) -> f64;
+} // Any horizontal current u(r, z) which gives a velocity for a given x position and depth below the surface.
+
+impl<F> CurrentField for F
+where
+    F: Fn(f64, f64) -> f64 + Send + Sync,
+{
+    fn velocity(&self, x: f64, depth: f64) -> f64 {
+        self(x, depth)
+    }
+} // Allows any closure of (x, depth) to be used directly as a current field.
+
+pub struct CurrentProfile {
+    depths: Vec<f64>,
+    velocities: Vec<f64>,
+}
+
+impl CurrentProfile {
+    pub fn new(points: Vec<[f64;2]>) -> Self {
+        if points.is_empty() {
+            eprintln!("Error: A current profile must contain at least one point.");
+            std::process::exit(1);
+        }
+        if points.windows(2).any(|pair| pair[1][0] <= pair[0][0]) {
+            eprintln!("Error: The depths of a current profile must be strictly increasing.");
+            std::process::exit(1);
+        }
+
+        Self {
+            depths: points.iter().map(|point| point[0]).collect(),
+            velocities: points.iter().map(|point| point[1]).collect(),
+        }
+    } // Initialisation function for a current u(z) from a list of [depth, velocity] points, after undergoing necessary error checks.
+}
+
+impl CurrentField for CurrentProfile {
+    fn velocity(&self, _x: f64, depth: f64) -> f64 {
+        let upper = self.depths.partition_point(|value| *value < depth);
+        if upper == 0 { return self.velocities[0] }
+        if upper == self.depths.len() { return self.velocities[self.velocities.len() - 1] }
+
+        let fraction = (depth - self.depths[upper - 1]) / (self.depths[upper] - self.depths[upper - 1]);
+        self.velocities[upper - 1] + fraction * (self.velocities[upper] - self.velocities[upper - 1])
+    } // Linearly interpolates between the points, holding the end velocities constant above and below the profile.
+}
+
+//                                                  MARK: Internal Waves
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuoyancyProfile {
+    Exponential { surface_frequency: f64, scale_depth: f64 },
+    Table(Vec<[f64;2]>),
+} // The buoyancy frequency N(z) in rad/s, either N0 exp(-z / B) or linearly interpolated [depth, frequency] points.
+
+impl BuoyancyProfile {
+    pub fn frequency(&self, depth: f64) -> f64 {
+        let depth = depth.max(0.0);
+        match self {
+            BuoyancyProfile::Exponential { surface_frequency, scale_depth } => surface_frequency * (-depth / scale_depth).exp(),
+            BuoyancyProfile::Table(points) => {
+                let upper = points.partition_point(|point| point[0] < depth);
+                if upper == 0 { return points[0][1] }
+                if upper == points.len() { return points[points.len() - 1][1] }
+
+                let fraction = (depth - points[upper - 1][0]) / (points[upper][0] - points[upper - 1][0]);
+                points[upper - 1][1] + fraction * (points[upper][1] - points[upper - 1][1])
+            }
+        }
+    }
+
+    pub fn stretched_depth(&self, depth: f64) -> f64 {
+        let depth = depth.max(0.0);
+        match self {
+            BuoyancyProfile::Exponential { surface_frequency, scale_depth } => surface_frequency * scale_depth * (1.0 - (-depth / scale_depth).exp()),
+            BuoyancyProfile::Table(points) => {
+                let mut integral = points[0][1] * depth.min(points[0][0]);
+                for pair in points.windows(2) {
+                    if depth <= pair[0][0] { break }
+                    let segment_end = depth.min(pair[1][0]);
+                    integral += 0.5 * (pair[0][1] + self.frequency(segment_end)) * (segment_end - pair[0][0]);
+                }
+                if depth > points[points.len() - 1][0] {
+                    integral += points[points.len() - 1][1] * (depth - points[points.len() - 1][0]);
+                }
+                integral
+            }
+        }
+    } // The WKB stretched depth, the integral of N(z) from the surface, which sets the shape of the internal wave modes.
+}
+
+#[derive(Clone, Copy, Debug)]
+struct InternalWaveComponent {
+    mode: f64,
+    amplitude: f64,
+    wavenumber: f64,
+    phase: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct InternalWaves {
+    energy_level: f64,
+    buoyancy: BuoyancyProfile,
+    water_depth: f64,
+    number_of_modes: usize,
+    components: Vec<InternalWaveComponent>,
+}
+
+impl InternalWaves {
+    pub fn garrett_munk(energy_level: f64, buoyancy: BuoyancyProfile, water_depth: f64, number_of_modes: usize, seed: u64) -> Self {
+        if energy_level < 0.0 {
+            eprintln!("Error: energy_level must be a positive float value.");
+            std::process::exit(1);
+        }
+        if water_depth <= 0.0 || number_of_modes == 0 {
+            eprintln!("Error: water_depth and number_of_modes must be positive and non-zero.");
+            std::process::exit(1);
+        }
+        if let BuoyancyProfile::Table(points) = &buoyancy {
+            if points.is_empty() || points.windows(2).any(|pair| pair[1][0] <= pair[0][0]) {
+                eprintln!("Error: A buoyancy profile table must contain at least one point, with strictly increasing depths.");
+                std::process::exit(1);
+            }
+        }
+
+        let mut internal_waves = Self {
+            energy_level,
+            buoyancy,
+            water_depth,
+            number_of_modes,
+            components: Vec::new(),
+        };
+        internal_waves.reseed(seed);
+        internal_waves
+    } // A frozen realisation of the Garrett-Munk spectrum, where an energy_level of 1.0 is the GM79 reference level.
+
+    pub fn reseed(&mut self, seed: u64) {
+        const FREQUENCIES_PER_MODE: usize = 16;
+        const MODE_SCALE: f64 = 3.0; // j*, the GM mode bandwidth
+
+        let mut random = Random::seed(seed);
+        let maximum_frequency = (0..=100).map(|i| self.buoyancy.frequency(i as f64 * self.water_depth / 100.0)).fold(0.0, f64::max);
+        let total_stretched_depth = self.buoyancy.stretched_depth(self.water_depth);
+        let frequency_ratio = (maximum_frequency / INERTIAL_FREQUENCY).max(1.0 + 1e-6);
+
+        let mut components = Vec::with_capacity(self.number_of_modes * FREQUENCIES_PER_MODE);
+        for j in 1..=self.number_of_modes {
+            let mode_weight = 1.0 / ((j as f64).powi(2) + MODE_SCALE.powi(2));
+
+            for m in 0..FREQUENCIES_PER_MODE {
+                let lower = INERTIAL_FREQUENCY * frequency_ratio.powf(m as f64 / FREQUENCIES_PER_MODE as f64);
+                let upper = INERTIAL_FREQUENCY * frequency_ratio.powf((m + 1) as f64 / FREQUENCIES_PER_MODE as f64);
+                let frequency = lower + random.uniform() * (upper - lower);
+                // Logarithmically spaced bands from the inertial frequency to the largest buoyancy frequency.
+
+                let frequency_spectrum = 2.0 / PI * INERTIAL_FREQUENCY / (frequency * (frequency.powi(2) - INERTIAL_FREQUENCY.powi(2)).sqrt());
+                let displacement_weight = (frequency.powi(2) - INERTIAL_FREQUENCY.powi(2)) / frequency.powi(2) * frequency_spectrum;
+
+                components.push(InternalWaveComponent {
+                    mode: j as f64,
+                    amplitude: mode_weight * displacement_weight * (upper - lower),
+                    wavenumber: j as f64 * PI * (frequency.powi(2) - INERTIAL_FREQUENCY.powi(2)).sqrt() / total_stretched_depth,
+                    phase: 2.0 * PI * random.uniform(),
+                });
+            }
+        }
+
+        let total_weight: f64 = components.iter().map(|component| component.amplitude).sum();
+        for component in components.iter_mut() {
+            component.amplitude = (2.0 * component.amplitude / total_weight).sqrt();
+        } // Normalises the components so that the displacement has unit variance before being scaled with depth.
+        self.components = components;
+    } // Regenerates the random realisation from a new seed, keeping the same spectrum.
+
+    pub fn displacement(&self, x: f64, depth: f64) -> f64 {
+        const GM_ENERGY: f64 = 6.3e-5;
+        const GM_SCALE_DEPTH: f64 = 1300.0; // m
+        const GM_REFERENCE_FREQUENCY: f64 = 5.24e-3; // rad s^-1
+
+        if depth < 0.0 || depth > self.water_depth { return 0.0 }
+        let buoyancy_frequency = self.buoyancy.frequency(depth).max(1.0e-4);
+        let rms_displacement = (self.energy_level * 0.5 * GM_ENERGY * GM_SCALE_DEPTH.powi(2) * GM_REFERENCE_FREQUENCY / buoyancy_frequency).sqrt();
+
+        let relative_depth = PI * self.buoyancy.stretched_depth(depth) / self.buoyancy.stretched_depth(self.water_depth);
+        let displacement: f64 = self.components.iter()
+            .map(|component| component.amplitude * (component.mode * relative_depth).sin() * (component.wavenumber * x + component.phase).cos())
+            .sum();
+
+        rms_displacement * 2.0_f64.sqrt() * displacement
+    } // Vertical displacement of the water (m) using WKB scaled modes, with a variance of 0.5 E b^2 N0 / N(z).
+
+    pub fn speed_perturbation(&self, background_speed: f64, x: f64, depth: f64) -> f64 {
+        const POTENTIAL_GRADIENT: f64 = 24.5; // mu, the potential sound speed gradient
+
+        background_speed * POTENTIAL_GRADIENT / GRAVITY * self.buoyancy.frequency(depth).powi(2) * self.displacement(x, depth)
+    } // The change in the speed of sound, dc = c mu N^2 zeta / g, from the displacement of the background gradient.
+}
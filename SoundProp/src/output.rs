@@ -0,0 +1,180 @@
+use std::{fs, path::{Path, PathBuf}};
+
+const OUTPUT_FILES: [(&str, &str); 17] = [
+    ("dataset", ".txt"), ("dataset", ".csv"), ("datasets", ".jsonl"), ("boundary", ".txt"),
+    ("transmission_loss", ".txt"), ("transmission_loss_", "Hz.txt"),
+    ("ensemble_mean", ".txt"), ("ensemble_variance", ".txt"), ("ensemble_coverage", ".txt"), ("ensemble_p", ".txt"),
+    ("receiver", ".csv"), ("receiver", "_arrivals.csv"), ("receiver", ".wav"),
+    ("ray_paths", ".csv"), ("ray_paths", ".json"), ("interactions", ".csv"), ("imageGif", ".gif"),
+]; // The names of the files a run writes, after the prefix, with an index or frequency between the two parts where there is one.
+
+//                                                  MARK: Overwrite Enum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overwrite {
+    Fail,
+    Version,
+    Clean,
+} // What to do when the output directory already holds the files of an earlier run with the same prefix.
+
+//                                                  MARK: Output Config Struct
+#[derive(Clone, Debug)]
+pub struct OutputConfig {
+    directory: PathBuf,
+    image_directory: PathBuf,
+    prefix: String,
+    overwrite: Overwrite,
+    run_prefix: String,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("./outputdata"),
+            image_directory: PathBuf::from("./outputImages"),
+            prefix: String::new(),
+            overwrite: Overwrite::Version,
+            run_prefix: String::new(),
+        }
+    }
+} // Writes into 'outputdata' and 'outputImages' in the working directory, keeping the files of earlier runs.
+
+impl OutputConfig {
+    pub fn set_directory(&mut self, directory: &str) {
+        self.directory = PathBuf::from(directory);
+    }
+
+    pub fn set_image_directory(&mut self, directory: &str) {
+        self.image_directory = PathBuf::from(directory);
+    }
+
+    pub fn set_prefix(&mut self, prefix: &str) {
+        self.prefix = prefix.to_string();
+    } // Every output file name starts with the prefix, so several runs can share a directory.
+
+    pub fn set_overwrite(&mut self, overwrite: Overwrite) {
+        self.overwrite = overwrite;
+    }
+
+    pub fn prepare(&mut self) {
+        create_directory(&self.directory);
+        self.run_prefix = self.prefix.clone();
+
+        match self.overwrite {
+            Overwrite::Fail => if !existing_files(&self.directory, &self.prefix).is_empty() {
+                eprintln!("Error: {} already holds the output files of a run with the prefix '{}'. Change the directory or prefix, or set another overwrite policy.",
+                    self.directory.display(), self.prefix);
+                std::process::exit(1);
+            },
+            Overwrite::Version => {
+                let mut version = 1;
+                while !existing_files(&self.directory, &self.run_prefix).is_empty() {
+                    self.run_prefix = format!("{}v{}_", self.prefix, version);
+                    version += 1;
+                }
+            } // Adds the first version number not used by an earlier run to the prefix.
+            Overwrite::Clean => {
+                for file in existing_files(&self.directory, &self.prefix) {
+                    if let Err(err) = fs::remove_file(&file) {
+                        eprintln!("Error deleting file {}: {}", file.display(), err);
+                        std::process::exit(1);
+                    }
+                }
+            } // Only the files written by a run with exactly this prefix are deleted, leaving everything else in place.
+        }
+    } // Creates the output directory if needed and applies the overwrite policy, deciding the prefix of the files of this run.
+
+    pub fn prepare_images(&self) {
+        create_directory(&self.image_directory);
+        let gif = self.image_file("imageGif.gif");
+        if self.overwrite == Overwrite::Fail && gif.exists() {
+            eprintln!("Error: {} already exists. Change the image directory or prefix, or set another overwrite policy.", gif.display());
+            std::process::exit(1);
+        }
+    } // Creates the image directory if needed. The GIF of this run uses the same prefix as its data files.
+
+    pub fn run_prefix(&self) -> &str {
+        &self.run_prefix
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn file(&self, filename: &str) -> PathBuf {
+        self.directory.join(format!("{}{}", self.run_prefix, filename))
+    } // The path of an output file of this run.
+
+    pub fn image_file(&self, filename: &str) -> PathBuf {
+        self.image_directory.join(format!("{}{}", self.run_prefix, filename))
+    }
+}
+
+fn create_directory(directory: &Path) {
+    if let Err(err) = fs::create_dir_all(directory) {
+        eprintln!("Error creating directory {}: {}", directory.display(), err);
+        std::process::exit(1);
+    }
+} // Creates the directory along with any missing parents. Absolute and relative paths are both allowed.
+
+fn existing_files(directory: &Path, prefix: &str) -> Vec<PathBuf> {
+    match fs::read_dir(directory) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+            .filter(|entry| entry.file_name().to_str().and_then(|name| name.strip_prefix(prefix)).is_some_and(is_output_file))
+            .map(|entry| entry.path())
+            .collect(),
+        Err(err) => {
+            eprintln!("Error reading directory {}: {}", directory.display(), err);
+            std::process::exit(1);
+        }
+    }
+} // The files in the directory written by a run with exactly this prefix.
+
+fn is_output_file(name: &str) -> bool {
+    OUTPUT_FILES.iter().any(|(stem, extension)| {
+        name.strip_prefix(stem).and_then(|name| name.strip_suffix(extension))
+            .is_some_and(|middle| middle.is_empty() || middle.chars().all(|c| c.is_ascii_digit() || c == '.'))
+    })
+} // Whether the name, after the prefix, is one SoundProp writes. A prefix of 'run1' does not match 'run10_dataset0.txt'.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_every_output_file() {
+        for name in ["dataset0.txt", "dataset12.csv", "datasets.jsonl", "boundary.txt", "transmission_loss.txt", "transmission_loss_1000.5Hz.txt",
+            "ensemble_p90.txt", "receiver0.csv", "receiver0_arrivals.csv", "receiver3.wav", "ray_paths.json", "interactions.csv", "imageGif.gif"] {
+            assert!(is_output_file(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn ignores_other_files() {
+        for name in ["notes.txt", "dataset0.txt.bak", "datasetA.txt", "transmission_loss_1kHz.txt", "receiver0_log.csv", "0_dataset0.txt", "v1_dataset0.txt"] {
+            assert!(!is_output_file(name), "{}", name);
+        }
+    } // Including the files of runs whose prefix only starts with the one given.
+
+    #[test]
+    fn only_finds_the_files_of_the_prefix() {
+        let directory = std::env::temp_dir().join(format!("soundprop_output_{}", std::process::id()));
+        create_directory(&directory);
+        for name in ["dataset0.txt", "transmission_loss_1000.5Hz.txt", "v1_dataset0.txt", "run1receiver0_arrivals.csv", "run10_dataset0.txt", "notes.txt"] {
+            fs::write(directory.join(name), "").unwrap();
+        }
+
+        let names = |prefix: &str| -> Vec<String> {
+            let mut names: Vec<String> = existing_files(&directory, prefix).iter().map(|file| file.file_name().unwrap().to_string_lossy().into_owned()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(""), ["dataset0.txt", "transmission_loss_1000.5Hz.txt"]);
+        assert_eq!(names("v1_"), ["v1_dataset0.txt"]);
+        assert_eq!(names("run1"), ["run1receiver0_arrivals.csv"]);
+        assert_eq!(names("run10_"), ["run10_dataset0.txt"]);
+        assert!(names("run2").is_empty());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
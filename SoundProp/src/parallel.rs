@@ -0,0 +1,48 @@
+use std::{ops::Range, sync::{atomic::{AtomicUsize, Ordering}, Mutex}, thread};
+
+const CHUNK_SIZE: usize = 1024;
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |threads| threads.get())
+} // Every core the machine has, or a single thread if this cannot be found.
+
+pub fn chunks(length: usize) -> Vec<Range<usize>> {
+    (0..length.div_ceil(CHUNK_SIZE)).map(|chunk| chunk * CHUNK_SIZE..((chunk + 1) * CHUNK_SIZE).min(length)).collect()
+} // Splits 0..length into chunks of a fixed size, so the work is shared out the same way however many threads there are.
+
+pub fn map<T, R, F>(items: Vec<T>, threads: usize, function: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    if threads <= 1 || items.len() <= 1 {
+        return items.into_iter().map(function).collect();
+    }
+
+    let length = items.len();
+    let next_item = AtomicUsize::new(0);
+    let items: Vec<Mutex<Option<T>>> = items.into_iter().map(|item| Mutex::new(Some(item))).collect();
+    let results: Vec<Mutex<Option<R>>> = (0..length).map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..threads.min(length) {
+            scope.spawn(|| loop {
+                let index = next_item.fetch_add(1, Ordering::Relaxed);
+                if index >= length { break }
+                let item = items[index].lock().unwrap().take().unwrap();
+                let result = function(item);
+                *results[index].lock().unwrap() = Some(result);
+            });
+        }
+    }); // Each thread takes the next item which has not been started, until there are none left.
+
+    results.into_iter().map(|result| result.into_inner().unwrap().unwrap()).collect()
+} // Maps each item on a pool of threads, returning the results in the order of the items so they do not depend on the number of threads.
+
+pub fn map_chunks<T, F>(length: usize, threads: usize, function: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(Range<usize>) -> T + Sync,
+{
+    map(chunks(length), threads, function)
+} // Maps each chunk of 0..length on a pool of threads.
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+//                                                  MARK: Path Format Enum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathFormat {
+    Csv,
+    Json,
+}
+
+impl PathFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+}
+
+//                                                  MARK: Ray Paths Struct
+#[derive(Clone, Copy, Debug)]
+pub struct PathPoint {
+    pub ray: usize,
+    pub parent: Option<usize>,
+    pub source: usize,
+    pub time: f64,
+    pub x: f64,
+    pub y: f64,
+    pub intensity: f64,
+} // The position (m, y upwards) and intensity (µPa²) of a ray at one time (s).
+
+#[derive(Clone, Debug)]
+pub struct RayPaths {
+    interval: usize,
+    format: PathFormat,
+    points: Vec<PathPoint>,
+}
+
+impl RayPaths {
+    pub fn new(interval: usize, format: PathFormat) -> Self {
+        Self {
+            interval,
+            format,
+            points: Vec::new(),
+        }
+    } // Records the position of every ray once every 'interval' time steps.
+
+    pub fn due(&self, step: usize) -> bool {
+        step.is_multiple_of(self.interval)
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn record(&mut self, point: PathPoint) {
+        self.points.push(point);
+    }
+
+    pub fn filename(&self) -> String {
+        format!("ray_paths.{}", self.format.extension())
+    }
+
+    pub fn encode(&self) -> String {
+        match self.format {
+            PathFormat::Csv => {
+                let mut output = String::from("ray,parent,source,time,x,y,intensity\n");
+                for point in &self.points {
+                    let parent = point.parent.map_or(String::new(), |parent| parent.to_string());
+                    output.push_str(&format!("{},{},{},{},{},{},{}\n", point.ray, parent, point.source, point.time, point.x, point.y, point.intensity));
+                }
+                output
+            } // One row per ray per recorded step. Rays launched by a source have no parent.
+            PathFormat::Json => {
+                let mut rays: BTreeMap<usize, Vec<&PathPoint>> = BTreeMap::new();
+                for point in &self.points { rays.entry(point.ray).or_default().push(point) }
+
+                let rays: Vec<String> = rays.into_iter().map(|(ray, points)| {
+                    let parent = points[0].parent.map_or("null".to_string(), |parent| parent.to_string());
+                    let source = points[0].source;
+                    let points: Vec<String> = points.iter()
+                        .map(|point| format!("[{},{},{},{}]", number(point.time), number(point.x), number(point.y), number(point.intensity)))
+                        .collect();
+                    format!("{{\"ray\":{},\"parent\":{},\"source\":{},\"points\":[{}]}}", ray, parent, source, points.join(","))
+                }).collect();
+                format!("{{\"columns\":[\"time\",\"x\",\"y\",\"intensity\"],\"rays\":[\n{}\n]}}\n", rays.join(",\n"))
+            } // One object per ray, in the order they were created, holding each point along its path.
+        }
+    } // Returns the recorded paths as the contents of a file.
+}
+
+pub fn number(value: f64) -> String {
+    if value.is_finite() { value.to_string() } else { "null".to_string() }
+} // JSON has no way of writing infinite or NaN values.
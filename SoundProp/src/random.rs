@@ -0,0 +1,33 @@
+use std::f64::consts::PI;
+
+//                                                  MARK: Random Struct
+#[derive(Clone, Copy, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn seed(seed: u64) -> Self {
+        Self {
+            state: seed,
+        }
+    } // The same seed always reproduces the same sequence, so stochastic environments can be regenerated exactly.
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    } // SplitMix64 generator, which is fast and has no dependencies.
+
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    } // Returns a value in the range [0, 1).
+
+    pub fn normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.uniform();
+        let u2 = self.uniform();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    } // Returns a normally distributed value with zero mean and unit variance (Box-Muller transform).
+}
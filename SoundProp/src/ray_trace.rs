@@ -1,13 +1,24 @@
-use std::{any::type_name, cmp::Ordering, collections::HashMap, fmt::format, fs::{self, File}, io::prelude::*, path::Path, process::{Command, Output}
+use std::{collections::{BTreeMap, HashMap}, fs::{self, File, OpenOptions}, io::prelude::*, path::Path, process::{Command, Output}, sync::Arc
 };
-use crate::material::{self, Material, MaterialType};
+use crate::{array::SourceArray, beam::BeamPattern, branching::{self, BranchControl}, dataset::{self, DatasetFormat, FrameMetadata, SourceSummary}, ensemble::EnsembleStatistics, events::{self, Event, Interaction, Interface, Termination}, material::{Material, MaterialType}, parallel, paths::{PathFormat, PathPoint, RayPaths}, random::Random, ocean::{BubbleLayer, CurrentField, InternalWaves, WaterColumn}, output::{OutputConfig, Overwrite}, receiver::{Arrival, Receiver}, spectrum::Spectrum, surface::{IceCover, Roughness, Scattering, SeaSurface}, trajectory::Trajectory, wav::{self, SampleFormat}, waveform::Waveform};
 
 pub const PI: f64 = 3.14159265358979323846264338327950288_f64;
+const REFERENCE_DISTANCE: f64 = 1.0; // m, the distance source levels are given at.
+const PROVISIONAL: usize = 1 << 63; // Rays created during a step are given ids from here, until they are numbered at the end of the step.
+
+#[derive(Clone, Copy)]
 pub enum SourceType {
     Point,
     Line,
 }
 
+#[derive(Clone, Copy)]
+pub enum Emission {
+    Once,
+    Interval(f64),
+    Continuous,
+} // When a source launches its rays. Every source emits at t = 0.0, then again after each interval or every time step.
+
 
 pub fn derivative(f: fn(f64)->f64 , x: f64) -> f64{
     let h: f64 = 0.0000001;
@@ -17,44 +28,151 @@ pub fn derivative(f: fn(f64)->f64 , x: f64) -> f64{
 
 
 //                                               MARK: Simulation Struct
-pub struct Simulation<F: SingleInputFunction> {
+pub struct Simulation {
     sources : Vec<Source>,
+    newest_sources: usize,
     grid: Grid,
+    band: Vec<f64>,
     rays: Rays,
-    boundaries: Vec<Boundary<F>>,
+    receivers: Vec<Receiver>,
+    wav_format: SampleFormat,
+    boundaries: Vec<Boundary>,
+    boundary_index: BoundaryIndex,
+    surface: SeaSurface,
+    water: WaterColumn,
+    source_jitter: [f64;2],
+    threads: usize,
+    branching: BranchControl,
+    refinement: Option<(f64, usize)>,
+    ray_paths: Option<RayPaths>,
+    log_events: bool,
+    output: OutputConfig,
+    dataset_format: DatasetFormat,
 }
 
-impl<F: SingleInputFunction> Simulation<F> {
+impl Simulation {
 
     pub fn new(square_size: f64, simulation_x_range: [f64;2], simulation_y_range: [f64;2]) -> Self {
         let grid = Grid::initialise(square_size, simulation_x_range, simulation_y_range);
         Self {
             sources : Vec::new(),
+            newest_sources : 0,
             grid : grid,
+            band : Vec::new(),
             rays : Default::default(),
+            receivers : Vec::new(),
+            wav_format : SampleFormat::Float32,
             boundaries : Vec::new(),
+            boundary_index : Default::default(),
+            surface : SeaSurface::flat(),
+            water : Default::default(),
+            source_jitter : [0.0, 0.0],
+            threads : parallel::default_threads(),
+            branching : Default::default(),
+            refinement : None,
+            ray_paths : None,
+            log_events : false,
+            output : Default::default(),
+            dataset_format : DatasetFormat::Text,
             // Defines all other 'child' structs under the parent. 'rays' has not yet been defined.
         }
     } // Initialisation function to define the fields inside of Simulation after undergoing necessary error checks.
 
-    pub fn add_source(&mut self, start_angle: f64, end_angle: f64, number_of_rays: i32, intensity: f64, frequency: f64, location: [f64;2], source_type: SourceType) -> () {
+    pub fn add_source(&mut self, start_angle: f64, end_angle: f64, number_of_rays: i32, source_level: f64, frequency: f64, location: [f64;2], source_type: SourceType) {
         if start_angle.abs() > PI || end_angle.abs() > PI {
             eprintln!("Error: Minimum and maximum angles must be within the range of -π to π.");
             std::process::exit(1);
         } // Checks if the minimum and maximum angles are within the range of +/- PI.
+        if !source_level.is_finite() {
+            eprintln!("Error: source_level must be a finite float value.");
+            std::process::exit(1);
+        }
 
-        let new_source: Source = Source::initialise(source_type, start_angle, end_angle, 
-            number_of_rays as usize, intensity, frequency, location);
+        let mut new_source: Source = Source::initialise(source_type, start_angle, end_angle, 
+            number_of_rays as usize, 10.0_f64.powf(source_level / 10.0), frequency, location);
+        // The source level (dB re 1 µPa @ 1 m) is kept as the mean square pressure at 1 m, in µPa^2.
+        new_source.group = self.sources.last().map_or(0, |source| source.group + 1);
         
+        self.newest_sources = self.sources.len();
         self.sources.push(new_source);
         // Adds new source to an array of sources under the Simulation struct.
     }
 
-    pub fn add_boundary(&mut self, material: MaterialType, shape_function: F) -> ()
-    where
-        F: SingleInputFunction + 'static,
-    {
-        let new_boundary = Boundary::initialise(Box::new(shape_function), material);
+    pub fn add_source_array(&mut self, start_angle: f64, end_angle: f64, number_of_rays: i32, source_level: f64, frequency: f64, location: [f64;2], source_type: SourceType, array: SourceArray) {
+        let first_source = self.sources.len();
+        for element in array.elements() {
+            self.add_source(start_angle, end_angle, number_of_rays, source_level + 20.0 * element.amplitude.log10(), frequency,
+                [location[0] + element.offset[0], location[1] + element.offset[1]], source_type);
+            let new_source = self.sources.last_mut().unwrap();
+            new_source.delay = element.delay;
+            new_source.array_offset = element.offset;
+        } // Each element is a source of its own, fired after its delay. The source level is that of an element with an amplitude of 1.0.
+
+        let group = self.sources[first_source].group;
+        for source in self.sources[first_source..].iter_mut() { source.group = group }
+        self.newest_sources = first_source;
+    } // Adds a source for every element of the array, which move together and are set up together by the 'source_' functions.
+
+    fn newest_sources(&mut self) -> &mut [Source] {
+        &mut self.sources[self.newest_sources..]
+    } // The last source added, or every element of the last source array.
+
+    pub fn source_spectrum(&mut self, spectrum: Spectrum) {
+        for source in self.newest_sources() {
+            source.spectrum = spectrum.clone();
+        }
+    } // Replaces the single frequency of the last source with a band or list of frequencies.
+
+    pub fn source_waveform(&mut self, waveform: Waveform) {
+        for source in self.newest_sources() {
+            source.waveform = waveform.clone();
+        }
+    } // Sets the signal which the last source emits, used to synthesise the pressure at each receiver.
+
+    pub fn source_trajectory(&mut self, trajectory: Trajectory) {
+        for source in self.newest_sources() {
+            let start = trajectory.start();
+            source.location = [start[0] + source.array_offset[0], start[1] + source.array_offset[1]];
+            source.trajectory = Some(trajectory.clone());
+        }
+    } // Moves the last source along the trajectory, starting from its first waypoint. The elements of an array keep their offsets from it.
+
+    pub fn source_beam(&mut self, pattern: BeamPattern, axis: f64) {
+        pattern.check();
+        for source in self.newest_sources() {
+            source.beam = pattern.clone();
+            source.beam_axis = axis;
+        }
+    } // Gives the last source a directivity, pointing along the axis angle (measured like the launch angles of the rays).
+
+    pub fn source_emission(&mut self, emission: Emission) {
+        if let Emission::Interval(period) = emission {
+            if period.is_nan() || period <= 0.0 {
+                eprintln!("Error: The emission interval must be a positive, non-zero, float value.");
+                std::process::exit(1);
+            }
+        }
+        for source in self.newest_sources() {
+            source.emission = emission;
+        }
+    } // Sets when the last source launches rays. By default a source only emits at t = 0.0.
+
+    pub fn add_receiver(&mut self, location: [f64;2], radius: f64, sample_rate: f64) {
+        self.receivers.push(Receiver::new(location, radius, sample_rate));
+    } // Records every ray which passes within the radius of the location.
+
+    pub fn receiver_trajectory(&mut self, trajectory: Trajectory) {
+        if let Some(last_receiver) = self.receivers.last_mut() {
+            last_receiver.set_trajectory(trajectory);
+        }
+    } // Moves the last receiver along the trajectory, starting from its first waypoint.
+
+    pub fn receiver_wav_format(&mut self, format: SampleFormat) {
+        self.wav_format = format;
+    } // Sets the sample format of the WAV files written for every receiver, which is 32-bit float by default.
+
+    pub fn add_boundary<S: BoundaryShape + 'static>(&mut self, material: MaterialType, shape: S) {
+        let new_boundary = Boundary::initialise(Arc::new(shape), material);
         self.boundaries.push( new_boundary.unwrap() );
     }
 
@@ -70,84 +188,425 @@ impl<F: SingleInputFunction> Simulation<F> {
         }
     }
 
+    pub fn roughness(&mut self, rms_height: f64, correlation_length: f64, scattering: Scattering) {
+        if let Some(last_boundary) = self.boundaries.last_mut() {
+            last_boundary.set_roughness(Roughness::new(rms_height, correlation_length, scattering));
+        }
+    }
+
+    pub fn surface_roughness(&mut self, rms_height: f64, correlation_length: f64, scattering: Scattering) {
+        self.surface.set_roughness(Roughness::new(rms_height, correlation_length, scattering));
+    } // Roughness is kept with the surface, so it should be called after set_sea_surface.
+
+    pub fn set_ice_cover(&mut self, thickness: f64, material: Material, attenuation: f64) {
+        self.surface.set_ice_cover(IceCover::new(thickness, material, attenuation));
+    } // The ice is kept with the surface, so it should be called after set_sea_surface.
+
+    pub fn ice_roughness(&mut self, rms_height: f64, correlation_length: f64, scattering: Scattering) {
+        if let Some(ice) = self.surface.ice_cover_mut() {
+            ice.set_roughness(Roughness::new(rms_height, correlation_length, scattering));
+        }
+    }
+
+    pub fn set_bubble_layer(&mut self, bubbles: BubbleLayer) {
+        self.water.set_bubble_layer(bubbles);
+    } // Adds wind driven bubbles near the surface, which slow the speed of sound and add extra loss.
+
+    pub fn set_internal_waves(&mut self, internal_waves: InternalWaves) {
+        self.water.set_internal_waves(internal_waves);
+    } // Adds a random internal wave field to the background speed of sound in the water.
+
+    pub fn set_current<C: CurrentField + 'static>(&mut self, current: C) {
+        self.water.set_current(Arc::new(current));
+    } // Adds a horizontal current, which carries the rays along with the water.
+
+    pub fn set_sea_surface(&mut self, surface: SeaSurface) {
+        self.surface = surface;
+    } // Replaces the default flat surface with a time-varying one.
+
+    pub fn source_jitter(&mut self, standard_deviation: [f64;2]) {
+        self.source_jitter = standard_deviation;
+    } // Randomly moves every source by a normally distributed offset [x, y] in each member of an ensemble.
+
+    pub fn set_threads(&mut self, threads: usize) {
+        if threads == 0 {
+            eprintln!("Error: threads must be a positive, non-zero, integer value.");
+            std::process::exit(1);
+        }
+        self.threads = threads;
+    } // Sets the number of threads the rays are stepped and superimposed on. By default every core is used.
+
+    pub fn max_split_depth(&mut self, depth: usize) {
+        self.branching.set_max_depth(depth);
+    } // Stops rays creating reflected rays once they have split this many times. Reflected rays that are not created take their energy with them.
+
+    pub fn branch_threshold(&mut self, threshold: f64) {
+        if !(0.0..1.0).contains(&threshold) {
+            eprintln!("Error: The branch threshold must be at least 0 and less than 1.");
+            std::process::exit(1);
+        }
+        self.branching.set_threshold(threshold);
+    } // Drops reflected rays weaker than this fraction of the strongest source at 1 m.
+
+    pub fn russian_roulette(&mut self, threshold: f64, survival: f64) {
+        if !(0.0..1.0).contains(&threshold) || survival <= 0.0 || survival > 1.0 {
+            eprintln!("Error: The roulette threshold must be at least 0 and less than 1, and the survival probability must be greater than 0 and no more than 1.");
+            std::process::exit(1);
+        }
+        self.branching.set_roulette(threshold, survival);
+    } // Reflected rays weaker than the threshold survive with the given probability, and those which survive are made stronger to make up for the rest.
+
+    pub fn ray_budget(&mut self, budget: usize) {
+        if budget == 0 {
+            eprintln!("Error: The ray budget must be a positive, non-zero, integer value.");
+            std::process::exit(1);
+        }
+        self.branching.set_budget(budget);
+    } // Resamples the rays at the end of any step which leaves more than this many.
+
+    pub fn refine_rays(&mut self, separation: f64, max_depth: usize) {
+        if separation <= 0.0 {
+            eprintln!("Error: The refinement separation must be a positive, non-zero, value.");
+            std::process::exit(1);
+        }
+        if max_depth == 0 {
+            eprintln!("Error: The maximum refinement depth must be a positive, non-zero, integer value.");
+            std::process::exit(1);
+        }
+        self.refinement = Some((separation, max_depth));
+    } // Adds a ray between any two neighbouring rays on the same wavefront which move further apart than this distance (m),
+    // until the gaps around each launched ray have been halved max_depth times.
+
+    pub fn record_ray_paths(&mut self, interval: usize, format: PathFormat) {
+        if interval == 0 {
+            eprintln!("Error: The ray path interval must be a positive, non-zero, integer value.");
+            std::process::exit(1);
+        }
+        self.ray_paths = Some(RayPaths::new(interval, format));
+    } // Records the position of every ray once every 'interval' time steps, written out at the end of generate_data_files.
+
+    pub fn log_interactions(&mut self) {
+        self.log_events = true;
+    } // Logs every reflection, transmission, total internal reflection and termination of a ray, written out at the end of generate_data_files.
+
+    pub fn output_directory(&mut self, directory: &str) {
+        self.output.set_directory(directory);
+    } // Sets the directory the data files are written into, relative to the working directory or absolute. By default this is './outputdata'.
+
+    pub fn image_directory(&mut self, directory: &str) {
+        self.output.set_image_directory(directory);
+    } // Sets the directory generate_gif writes the GIF into. By default this is './outputImages'.
+
+    pub fn file_prefix(&mut self, prefix: &str) {
+        if prefix.contains(['/', '\\']) {
+            eprintln!("Error: The file prefix must not contain a path separator. Use output_directory to change the directory.");
+            std::process::exit(1);
+        }
+        self.output.set_prefix(prefix);
+    } // Starts the name of every output file with the prefix, so runs can share a directory.
+
+    pub fn overwrite_policy(&mut self, overwrite: Overwrite) {
+        self.output.set_overwrite(overwrite);
+    } // Decides what happens to the files of an earlier run with the same prefix. By default they are kept, and the new files are given a version number.
+
+    pub fn dataset_format(&mut self, format: DatasetFormat) {
+        self.dataset_format = format;
+    } // Sets how the received level is written at each frame. CSV and JSON Lines carry the time, grid, sources, boundaries and version with each frame.
+
     pub fn generate_data_files(&mut self, duration: f64, dt: f64, number_of_files: i32) -> f64 {
         if self.sources.len() == 0 {
             eprintln!("Error: No sources have been defined. Call 'self.addSource' prior to this function to define a soundwave source.");
             std::process::exit(1);
         } // Ensures that a source has been defined prior to this function.
         
-        self.create_folder("./outputdata");
+        self.output.prepare();
         // Creates folder for data files to be stored
 
         let size: i32 = (duration / dt) as i32;
         let frame_spacing: i32 = size / number_of_files;
-        let mut max_init_intensity = 0.0;
-        let number_of_rays: usize = self.sources.iter().map(|source| source.number_of_rays as usize).sum();
-        //Sums 'number_of_rays' across all sources.
-
-        self.rays = Rays::initialise(number_of_rays);
-        // Defines the Rays struct with each variable inside having an appendable vector with minimum array size (beneficial for memory).
-        
-        for i in 0..self.sources.len() {
-            self.sources[i].create_rays(&mut self.rays);
-            if self.sources[i].intensity / (self.sources[i].number_of_rays as f64).powi(2) > max_init_intensity {
-                max_init_intensity = self.sources[i].intensity / (self.sources[i].number_of_rays as f64).powi(2);
-            }
-        } // Compiles all of the initial data for each ray, from its sources, into one 'Rays' struct.
-
-        self.rays.bound_angles([0,self.rays.x_pos.len()]);
+        let max_init_intensity = self.launch_rays();
+        let mut peak_intensity: Vec<BTreeMap<(usize, usize), f64>> = vec![BTreeMap::new(); self.band.len() + 1];
+        if let Some(paths) = self.ray_paths.as_mut() { paths.clear() }
+        // The peak intensity in each grid square for the whole band, followed by each frequency in it. The squares are kept in order, so the files are the same on every run.
 
         for i in 0..size {
             if i != 0{
-                self.grid.squares.clear();
-                self.rays.step(dt, &mut self.boundaries, self.grid.x_range, self.grid.y_range, max_init_intensity);  
+                self.step_rays(dt, i as f64 * dt, max_init_intensity);
+                self.emit_rays(i as f64 * dt, dt);
             } // Done to ensure that the initial positions of the rays is not overwritten in the output file.
+            if let Some(paths) = self.ray_paths.as_mut().filter(|paths| paths.due(i as usize)) {
+                self.rays.record_paths(paths, i as f64 * dt);
+            }
             if (i % frame_spacing) == 0 {
-                for j in 0..self.rays.x_pos.len() {
-                    let phase = self.rays.output_phase(j as usize);
-                    self.grid.append([self.rays.x_pos[j], self.rays.y_pos[j]], self.rays.intensity[j], phase);
-                    // Adds the intensity and phase shift to a specific 'grid square' (location defined by ray position).
-                }
-                let (xpos, ypos, intensity) = self.grid.output_data();
-                self.output(xpos, ypos, Some(intensity), format!("/dataset{}", i / frame_spacing));
-                // Outputs the intensitys at each grid square to a file
+                self.superimpose_rays();
+                let (band_intensities, frequency_intensities) = self.superimposed_intensities();
+                if !self.band.is_empty() {
+                    for (intensities, peaks) in std::iter::once(&band_intensities).chain(frequency_intensities.iter()).zip(peak_intensity.iter_mut()) {
+                        for (square, intensity) in intensities {
+                            let peak = peaks.entry(*square).or_insert(0.0);
+                            if *intensity > *peak { *peak = *intensity }
+                        }
+                    }
+                } // Only broadband runs keep track of the peak intensities, for their transmission loss grids.
+
+                let received_levels: Vec<((usize, usize), f64)> = band_intensities.into_iter().filter(|(_, intensity)| *intensity > 0.0)
+                    .map(|(square, intensity)| (square, 10.0 * intensity.log10())).collect();
+                let (xpos, ypos, level) = self.grid.output_data(received_levels);
+                self.output_frame((i / frame_spacing) as usize, i as f64 * dt, &xpos, &ypos, &level);
+                // Outputs the received level (dB re 1 µPa) at each grid square to a file
             }
         } // Time loop which pushes each ray by one step and outputs the new positions each iteration.
 
-        max_init_intensity
-    }
+        if !self.band.is_empty() {
+            let mut references = vec![max_init_intensity];
+            references.extend(self.band.iter().map(|frequency| self.sources.iter()
+                .map(|source| source.intensity * source.spectrum.level_at(*frequency))
+                .fold(0.0, f64::max)));
+            // Each frequency is compared against the strongest source at that frequency.
+
+            let mut filenames = vec!["transmission_loss".to_string()];
+            filenames.extend(self.band.iter().map(|frequency| format!("transmission_loss_{}Hz", frequency)));
+
+            for ((peaks, reference), filename) in peak_intensity.into_iter().zip(references).zip(filenames) {
+                let transmission_loss: Vec<((usize, usize), f64)> = peaks.into_iter().filter(|(_, intensity)| *intensity > 0.0)
+                    .map(|(square, intensity)| (square, 10.0 * (reference / intensity).log10())).collect();
+                let (xpos, ypos, loss) = self.grid.output_data(transmission_loss);
+                self.output(xpos, ypos, Some(loss), filename);
+            }
+        } // Outputs the band averaged and per-frequency transmission loss (dB) from the peak intensity in each grid square.
 
-//                                                    MARK: Outputs
+        self.output_receivers();
+        if let Some(paths) = &self.ray_paths {
+            self.write_file(paths.filename(), paths.encode().as_bytes());
+        }
+        if let Some(events) = &self.rays.events {
+            self.write_file("interactions.csv".to_string(), events::encode(events).as_bytes());
+        }
 
-    fn create_folder(&mut self, folder_path: &str) {
-        let path = Path::new(folder_path);
+        10.0 * max_init_intensity.log10()
+    } // Returns the level of the strongest source, in dB re 1 µPa @ 1 m.
 
-        // Check if the provided path is absolute
-        if path.is_absolute() {
-            eprintln!("Error: Absolute paths are not allowed.");
+    pub fn run_ensemble(&mut self, members: usize, duration: f64, dt: f64, number_of_samples: i32, seed: u64, percentiles: &[f64]) -> EnsembleStatistics {
+        if self.sources.len() == 0 {
+            eprintln!("Error: No sources have been defined. Call 'self.addSource' prior to this function to define a soundwave source.");
             std::process::exit(1);
         }
+        if members == 0 || number_of_samples <= 0 {
+            eprintln!("Error: members and number_of_samples must be positive, non-zero, integer values.");
+            std::process::exit(1);
+        }
+        if percentiles.iter().any(|percentile| !(0.0..=100.0).contains(percentile)) {
+            eprintln!("Error: Percentiles must be within the range of 0.0 to 100.0.");
+            std::process::exit(1);
+        }
+
+        self.output.prepare();
 
-        // Close and delete the directory
-        if path.exists() {
-            drop(fs::read_dir(folder_path));
-            match fs::remove_dir_all(folder_path) {
-                Ok(_) => (),
-                Err(err) => {
-                    eprintln!("Error deleting directory {}: {}", folder_path, err);
-                    std::process::exit(1);
+        let size: i32 = (duration / dt) as i32;
+        let sample_spacing: i32 = (size / number_of_samples).max(1);
+        let mut random = Random::seed(seed);
+        let source_locations: Vec<[f64;2]> = self.sources.iter().map(|source| source.location).collect();
+        let number_of_groups = self.sources.last().map_or(0, |source| source.group + 1);
+        let mut transmission_loss: BTreeMap<(usize, usize), Vec<f64>> = BTreeMap::new();
+
+        for member in 0..members {
+            self.surface.reseed(random.next_u64());
+            self.water.reseed(random.next_u64());
+            let jitter: Vec<[f64;2]> = (0..number_of_groups).map(|_| [self.source_jitter[0] * random.normal(), self.source_jitter[1] * random.normal()]).collect();
+            for (source, location) in self.sources.iter_mut().zip(&source_locations) {
+                source.location = [location[0] + jitter[source.group][0], location[1] + jitter[source.group][1]];
+            } // Draws a new realisation of every stochastic part of the environment for this member. The elements of an array move together.
+
+            let max_init_intensity = self.launch_rays();
+            let mut peak_intensity: BTreeMap<(usize, usize), f64> = BTreeMap::new();
+
+            for i in 0..size {
+                if i != 0 {
+                    self.step_rays(dt, i as f64 * dt, max_init_intensity);
+                    self.emit_rays(i as f64 * dt, dt);
+                }
+                if (i % sample_spacing) == 0 {
+                    self.superimpose_rays();
+                    for (square, intensity) in self.superimposed_intensities().0 {
+                        let peak = peak_intensity.entry(square).or_insert(0.0);
+                        if intensity > *peak { *peak = intensity }
+                    }
+                }
+            } // Records the peak intensity in each grid square over the whole run.
+
+            for (square, intensity) in peak_intensity {
+                if intensity > 0.0 {
+                    transmission_loss.entry(square).or_insert(vec![f64::INFINITY; members])[member] = 10.0 * (max_init_intensity / intensity).log10();
                 }
+            } // Grid squares which no ray reached in this member keep an infinite transmission loss.
+        }
+
+        for (source, location) in self.sources.iter_mut().zip(source_locations) {
+            source.location = location;
+        }
+
+        let statistics = EnsembleStatistics::calculate(transmission_loss.into_iter().map(|(square, values)| (self.grid.square_centre(square), values)).collect(), percentiles);
+
+        self.output(statistics.x_positions.clone(), statistics.y_positions.clone(), Some(statistics.mean.clone()), "ensemble_mean".to_string());
+        self.output(statistics.x_positions.clone(), statistics.y_positions.clone(), Some(statistics.variance.clone()), "ensemble_variance".to_string());
+        self.output(statistics.x_positions.clone(), statistics.y_positions.clone(), Some(statistics.coverage.clone()), "ensemble_coverage".to_string());
+        for (percentile, values) in &statistics.percentiles {
+            let reached: Vec<usize> = (0..values.len()).filter(|j| values[*j].is_finite()).collect();
+            self.output(reached.iter().map(|j| statistics.x_positions[*j]).collect(), reached.iter().map(|j| statistics.y_positions[*j]).collect(),
+                Some(reached.iter().map(|j| values[*j]).collect()), format!("ensemble_p{}", percentile));
+        } // Outputs the transmission loss statistics, leaving out grid squares with an infinite percentile.
+
+        statistics
+    } // Repeats the simulation with new realisations of the stochastic environment, giving transmission loss statistics for each grid square.
+
+    fn launch_rays(&mut self) -> f64 {
+        let mut max_init_intensity = 0.0;
+        let number_of_rays: usize = self.sources.iter().map(|source| source.number_of_rays as usize).sum();
+        //Sums 'number_of_rays' across all sources.
+
+        self.rays = Rays::initialise(number_of_rays);
+        if self.log_events { self.rays.events = Some(Vec::new()) }
+        // Defines the Rays struct with each variable inside having an appendable vector with minimum array size (beneficial for memory).
+        
+        for receiver in self.receivers.iter_mut() { receiver.clear() }
+        self.boundary_index = BoundaryIndex::build(&self.boundaries, self.grid.x_range, self.grid.square_size);
+        // The boundaries cannot change during a run, so the order of their interfaces is worked out once.
+
+        for i in 0..self.sources.len() {
+            let first_ray = self.rays.x_pos.len();
+            if self.sources[i].delay == 0.0 { self.sources[i].create_rays(&mut self.rays, i, 0.0) }
+            self.rays.bound_angles([first_ray, self.rays.x_pos.len()]);
+            self.shape_rays(i, [first_ray, self.rays.x_pos.len()], 0.0);
+            // Each source only shapes the rays it has just launched.
+            if self.sources[i].intensity > max_init_intensity {
+                max_init_intensity = self.sources[i].intensity;
             }
+        } // Compiles all of the initial data for each ray, from its sources, into one 'Rays' struct.
+
+        self.band.clear();
+        if self.sources.iter().any(|source| source.spectrum.frequencies().len() > 1) {
+            let mut frequencies: Vec<f64> = self.sources.iter().flat_map(|source| source.spectrum.frequencies().to_vec()).collect();
+            frequencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            frequencies.dedup();
+            self.band = frequencies;
         }
-        // Create the new directory
-        match fs::create_dir(folder_path) {
-            Ok(_) => (),
-            Err(err) => {
-                eprintln!("Error creating directory {}: {}", folder_path, err);
-                std::process::exit(1);
+        self.grid.set_channels(self.band.len().max(1));
+        // Broadband runs superimpose each frequency separately, as different frequencies do not interfere.
+
+        max_init_intensity
+    } // Creates the initial rays from every source, returning the intensity of the strongest source at 1 m.
+
+    fn emit_rays(&mut self, time: f64, dt: f64) {
+        for i in 0..self.sources.len() {
+            if let Some(emission_time) = self.sources[i].emission_time(time, dt) {
+                let first_ray = self.rays.x_pos.len();
+                self.sources[i].create_rays(&mut self.rays, i, emission_time);
+                self.rays.bound_angles([first_ray, self.rays.x_pos.len()]);
+                self.shape_rays(i, [first_ray, self.rays.x_pos.len()], emission_time);
+                self.advance_rays(first_ray, time - emission_time, emission_time);
             }
         }
-    }
+    } // Launches new rays from every source which emitted during the time step ending at this time.
+
+    fn step_rays(&mut self, dt: f64, time: f64, init_max_intensity: f64) {
+        let context = StepContext {
+            boundaries: &self.boundaries,
+            index: &self.boundary_index,
+            surface: &self.surface,
+            water: &self.water,
+            x_limit: self.grid.x_range,
+            y_limit: self.grid.y_range,
+            init_max_intensity,
+            branching: &self.branching,
+            refinement: self.refinement,
+            threads: self.threads,
+        };
+        self.rays.step(dt, time, &context, &mut self.receivers);
+    } // Moves every ray forward by one time step through the environment of the simulation.
+
+    fn advance_rays(&mut self, first_ray: usize, elapsed: f64, time: f64) {
+        if elapsed <= 0.0 { return }
+        for j in first_ray..self.rays.x_pos.len() {
+            let (speed, _) = self.rays.ray_speed(self.rays.x_pos[j], self.rays.y_pos[j], &self.boundaries, &self.boundary_index, &self.surface, &self.water, time);
+            self.rays.x_pos[j] += self.rays.step_vector[j] * self.rays.angle[j].sin() * speed * elapsed;
+            self.rays.y_pos[j] += self.rays.step_vector[j] * self.rays.angle[j].cos() * speed * elapsed;
+            self.rays.propagation_time[j] = elapsed;
+            self.rays.total_distance[j] += speed * elapsed;
+        }
+    } // Moves rays emitted part way through a time step in a straight line, so they keep in step with the rest.
+
+    fn shape_rays(&mut self, source_index: usize, ray_range: [usize;2], time: f64) {
+        let velocity = self.sources[source_index].velocity(time);
+        let beam = self.sources[source_index].beam.clone();
+        if velocity == [0.0, 0.0] && beam == BeamPattern::Omnidirectional { return }
+
+        let frequencies = self.sources[source_index].spectrum.frequencies();
+        let centre_frequency = (frequencies[0] * frequencies[frequencies.len() - 1]).sqrt();
+        let axis = self.sources[source_index].beam_axis;
+
+        for j in ray_range[0]..ray_range[1] {
+            let (speed, _) = self.rays.ray_speed(self.rays.x_pos[j], self.rays.y_pos[j], &self.boundaries, &self.boundary_index, &self.surface, &self.water, time);
+            let direction = [self.rays.step_vector[j] * self.rays.angle[j].sin(), -self.rays.step_vector[j] * self.rays.angle[j].cos()];
+            self.rays.doppler[j] = 1.0 / (1.0 - (velocity[0] * direction[0] + velocity[1] * direction[1]) / speed);
+            // Rays launched ahead of a moving source are shifted up in frequency, and those launched behind it are shifted down.
+
+            if beam != BeamPattern::Omnidirectional {
+                let launch_angle = (self.rays.step_vector[j] * self.rays.angle[j].sin()).atan2(self.rays.step_vector[j] * self.rays.angle[j].cos());
+                let off_axis = (launch_angle - axis + PI).rem_euclid(2.0 * PI) - PI;
+                self.rays.scale_spectrum(j, |frequency| beam.response(off_axis, 2.0 * PI * frequency / speed).0);
+                self.rays.phase_shift[j] += beam.response(off_axis, 2.0 * PI * centre_frequency / speed).1;
+            } // Each frequency is weighted by the beam pattern. The phase is taken at the centre of the band.
+        }
+    } // Applies the motion and beam pattern of a source to the rays it has just launched, given as the range [first, end) of their indices.
+
+    fn superimpose_rays(&mut self) {
+        let rays = &self.rays;
+        let sources = &self.sources;
+        let square_size = self.grid.square_size;
+        let band_frequencies = &self.band;
+
+        let chunks = parallel::map_chunks(rays.x_pos.len(), self.threads, |range| {
+            let mut pressures: Vec<(usize, [f64;2], [f64;2])> = Vec::with_capacity(range.len());
+            for j in range {
+                let location = [rays.x_pos[j], -rays.y_pos[j]];
+                let weight = rays.sampling_weight(j, square_size);
+                let delay = sources[rays.source[j]].delay;
+                if band_frequencies.is_empty() {
+                    pressures.push((0, location, Grid::pressure(rays.intensity[j] * weight, rays.output_phase(j, 0, delay))));
+                    continue;
+                }
+                for k in 0..rays.frequencies[j].len() {
+                    let index = band_frequencies.partition_point(|band_frequency| *band_frequency < rays.frequencies[j][k]);
+                    pressures.push((index, location, Grid::pressure(rays.spectral_intensity[j][k] * weight, rays.output_phase(j, k, delay))));
+                } // Each frequency carried by a ray is added to the pressure of the grid square at that frequency.
+            }
+            pressures
+        }); // Works out the pressure of every ray (location defined by ray position, with y upwards) on each thread.
+
+        self.grid.clear();
+        for (index, location, pressure) in chunks.into_iter().flatten() {
+            self.grid.append(location, index, pressure);
+        } // The pressures are added in the order of the rays, so the sums do not depend on the number of threads.
+    } // Fills the grid with the current intensity and phase of every ray.
+
+    fn superimposed_intensities(&self) -> (Vec<((usize, usize), f64)>, Vec<Vec<((usize, usize), f64)>>) {
+        if self.band.is_empty() {
+            return (self.grid.superimposed_intensities(0), Vec::new());
+        }
+
+        let frequency_intensities: Vec<Vec<((usize, usize), f64)>> = (0..self.band.len()).map(|k| self.grid.superimposed_intensities(k)).collect();
+        let mut band_intensities = frequency_intensities[0].clone();
+        for intensities in &frequency_intensities[1..] {
+            for (band_intensity, (_, intensity)) in band_intensities.iter_mut().zip(intensities) {
+                band_intensity.1 += intensity;
+            }
+        } // Every frequency is kept for the same squares, in the same order. As different frequencies do not interfere,
+        // the intensity of the band is the sum of the intensity at each frequency, from every source.
+
+        (band_intensities, frequency_intensities)
+    } // Returns the superimposed intensity in each grid square for the whole band, then for each frequency in a broadband run.
+
+//                                                    MARK: Outputs
 
     fn output(&mut self, xpos: Vec<f64>, ypos: Vec<f64>, additional_data: Option<Vec<f64>>, filename: String) -> () {
             let mut output = String::new();
@@ -158,39 +617,87 @@ impl<F: SingleInputFunction> Simulation<F> {
                 for i in 0..xpos.len() { output.push_str(&format!("{} {} {}\n", xpos[i], ypos[i], intensity[i])) }
             } else { for i in 0..xpos.len() { output.push_str(&format!("{} {}\n", xpos[i], ypos[i])) } }
 
-            let folder_path = "./outputdata";
-            // Define the folder path where output files will be stored
+            self.write_file(format!("{}.txt", filename), output.as_bytes());
+    }
 
-            let file_name = format!("{}{}.txt", folder_path, filename);
-            // Define the file name with the folder path and the index 'i'
-    
-            let mut file = match File::create(&file_name) {
-                Ok(file) => file,
-                Err(err) => {
-                    eprintln!("Error creating file {}: {}", &file_name, err);
-                    std::process::exit(1);
-                }
-            }; // Create or open the file for writing
+    fn output_frame(&mut self, frame: usize, time: f64, xpos: &[f64], ypos: &[f64], level: &[f64]) {
+        let metadata = FrameMetadata {
+            frame,
+            time,
+            square_size: self.grid.square_size,
+            x_range: self.grid.x_range,
+            y_range: self.grid.y_range,
+            sources: self.sources.iter().map(|source| SourceSummary {
+                source_type: match source.source_type { SourceType::Point => "Point", SourceType::Line => "Line" },
+                location: source.location,
+                level: 10.0 * source.intensity.log10(),
+                number_of_rays: source.number_of_rays,
+            }).collect(),
+            boundaries: self.boundaries.iter().map(|boundary| boundary.material.material_type()).collect(),
+        };
+        let contents = dataset::encode(self.dataset_format, &metadata, xpos, ypos, level);
+
+        match self.dataset_format {
+            DatasetFormat::JsonLines if frame != 0 => self.append_file(self.dataset_format.filename(frame), contents.as_bytes()),
+            _ => self.write_file(self.dataset_format.filename(frame), contents.as_bytes()),
+        } // Each frame of a JSON Lines run is added to the end of the file started by the first.
+    } // Outputs the received level (dB re 1 µPa) at each grid square reached, at one frame.
+
+    fn write_file(&self, filename: String, contents: &[u8]) {
+            let file_name = self.output.file(&filename);
+            // Define the file name with the output directory and prefix
     
-            if let Err(err) = file.write_all(output.as_bytes()) {
-                eprintln!("Error writing to file {}: {}", &file_name, err);
-                std::process::exit(1);
-            } // Write the output string to the file
+            let file = File::create(&file_name);
+            // Create or open the file for writing
+
+            write_contents(&file_name, file, contents);
+    }
+
+    fn append_file(&self, filename: String, contents: &[u8]) {
+            let file_name = self.output.file(&filename);
+            let file = OpenOptions::new().append(true).open(&file_name);
+            write_contents(&file_name, file, contents);
     }
 
+    fn output_receivers(&mut self) {
+        let waveforms: Vec<Waveform> = self.sources.iter().map(|source| source.waveform.clone()).collect();
+
+        for (r, receiver) in self.receivers.iter().enumerate() {
+            let mut arrivals = String::from("source,emission_time,delay,amplitude,phase,doppler,surface_bounces,bottom_bounces\n");
+            for arrival in receiver.arrivals() {
+                arrivals.push_str(&format!("{},{},{},{},{},{},{},{}\n", arrival.source, arrival.emission_time, arrival.delay, arrival.amplitude, arrival.phase, arrival.doppler,
+                    arrival.surface_bounces, arrival.bottom_bounces));
+            }
+            self.write_file(format!("receiver{}_arrivals.csv", r), arrivals.as_bytes());
+
+            let pressure = receiver.synthesise(&waveforms);
+            let mut time_series = String::from("time,pressure\n");
+            for (n, value) in pressure.iter().enumerate() {
+                time_series.push_str(&format!("{},{}\n", n as f64 / receiver.sample_rate(), value));
+            }
+            self.write_file(format!("receiver{}.csv", r), time_series.as_bytes());
+            self.write_file(format!("receiver{}.wav", r), &wav::encode(receiver.sample_rate(), &pressure, self.wav_format));
+        }
+    } // Outputs the arrivals at each receiver, and the pressure synthesised from them, as CSV and WAV files.
+
     pub fn generate_gif(&mut self, duration: f64, dt: f64, frames: i32) -> Output {
         if frames as f64 > (duration / dt) {
             eprintln!("Error: There is not enough time steps to accomodate the requested number of frames. Consider decreasing dt or frames.");
             std::process::exit(1);
         } // Terminates the program if the number of frames requested is greater than the maximum possible number of files produced
+        if self.dataset_format != DatasetFormat::Text {
+            eprintln!("Error: The GIF is drawn from the text data files. Use DatasetFormat::Text with generate_gif.");
+            std::process::exit(1);
+        }
 
-        let max_intensity = self.generate_data_files(duration, dt, frames);
-        let txt_files = match fs::read_dir("outputdata") {
+        let max_level = self.generate_data_files(duration, dt, frames);
+        let dataset_prefix = format!("{}dataset", self.output.run_prefix());
+        let txt_files = match fs::read_dir(self.output.directory()) {
             Ok(entries) => {
                 entries.filter_map(|entry| {
                     if let Ok(entry) = entry {
                         if let Some(extension) = entry.path().extension() {
-                            if extension == "txt" {
+                            if extension == "txt" && entry.file_name().to_string_lossy().strip_prefix(&dataset_prefix).is_some_and(|index| index.trim_end_matches(".txt").parse::<usize>().is_ok()) {
                                 return Some(entry.path());
                             }
                         }
@@ -202,11 +709,11 @@ impl<F: SingleInputFunction> Simulation<F> {
             Err(e) => {
                 eprintln!("Error: Failed to read directory: {}", e);
                 std::process::exit(1);
-            } // Terminates the program if directory 'outputdata' is not detected
+            } // Terminates the program if the output directory is not detected
         };
     
         if txt_files.is_empty() {
-            eprintln!("Error: No .txt files found in the outputdata folder");
+            eprintln!("Error: No .txt files found in the output directory");
             std::process::exit(1);
         } // Terminates the program if the directory does not contain .txt files.
 
@@ -241,14 +748,14 @@ impl<F: SingleInputFunction> Simulation<F> {
                 boundary_x.insert(0, boundary_x[0] - (self.grid.x_range[1] - self.grid.x_range[0]) / 1000.0);
                 boundary_y.insert(0, self.grid.y_range[0]);
             }
-            self.output(boundary_x, boundary_y, None, format!("/boundary{}", i));
+            self.output(boundary_x, boundary_y, None, format!("boundary{}", i));
         }
         
-        self.create_folder("./outputImages");
+        self.output.prepare_images();
 
         let length = txt_files.len();
-        let cmd = format!("runGifMAker.bat {} {} {} {} {} {} {} {}",
-         length, self.boundaries.len(), self.grid.x_range[0], self.grid.x_range[1], self.grid.y_range[0], self.grid.y_range[1], duration, max_intensity);
+        let cmd = format!("runGifMAker.bat \"{}\" \"{}\" {} {} {} {} {} {} {} {}",
+         self.output.file("").display(), self.output.image_file("imageGif.gif").display(), length, self.boundaries.len(), self.grid.x_range[0], self.grid.x_range[1], self.grid.y_range[0], self.grid.y_range[1], duration, max_level);
 
         if cfg!(target_os = "windows") {
             Command::new("cmd")
@@ -267,14 +774,38 @@ impl<F: SingleInputFunction> Simulation<F> {
 }
 
 
+fn write_contents(file_name: &Path, file: std::io::Result<File>, contents: &[u8]) {
+    let mut file = match file {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Error creating file {}: {}", file_name.display(), err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = file.write_all(contents) {
+        eprintln!("Error writing to file {}: {}", file_name.display(), err);
+        std::process::exit(1);
+    } // Write the output string to the file
+}
+
+
 //                                                 MARK: Source Struct
 pub struct Source {
     source_type : SourceType,
     angle_range : [f64;2],
     number_of_rays : usize,
-    intensity : f64,
-    frequency : f64,
+    intensity : f64, // Mean square pressure at 1 m (µPa^2)
+    spectrum : Spectrum,
+    waveform : Waveform,
     location : [f64;2],
+    trajectory : Option<Trajectory>,
+    emission : Emission,
+    beam : BeamPattern,
+    beam_axis : f64,
+    delay : f64,
+    array_offset : [f64;2],
+    group : usize,
 }
 
 impl Source {
@@ -302,12 +833,51 @@ impl Source {
             angle_range : [start_angle, end_angle],
             number_of_rays : number_of_rays,
             intensity : intensity,
-            frequency : frequency,
+            spectrum : Spectrum::tone(frequency),
+            waveform : Waveform::Ricker { peak_frequency: frequency },
             location : location,
+            trajectory : None,
+            emission : Emission::Once,
+            beam : BeamPattern::Omnidirectional,
+            beam_axis : 0.0,
+            delay : 0.0,
+            array_offset : [0.0, 0.0],
+            group : 0,
         }
     } // Initialisation function to define the fields inside of Struct after undergoing necessary error checks.
 
-    fn create_rays(&mut self, initial_rays: &mut Rays) {
+    fn position(&self, time: f64) -> [f64;2] {
+        match &self.trajectory {
+            Some(trajectory) => {
+                let [x, y] = trajectory.position(time);
+                let start = trajectory.start();
+                [self.location[0] + x - start[0], self.location[1] + y - start[1]]
+            } // Measured from 'location', so the source can still be moved in an ensemble.
+            None => self.location,
+        }
+    }
+
+    fn velocity(&self, time: f64) -> [f64;2] {
+        match &self.trajectory {
+            Some(trajectory) => trajectory.velocity(time),
+            None => [0.0, 0.0],
+        }
+    }
+
+    fn emission_time(&self, time: f64, dt: f64) -> Option<f64> {
+        let since_delay = time - self.delay;
+        if since_delay < 0.0 { return None }
+
+        let last_emission = self.delay + match self.emission {
+            Emission::Once => 0.0,
+            Emission::Interval(period) => (since_delay / period).floor() * period,
+            Emission::Continuous => return Some(time),
+        };
+        if last_emission > time - dt { Some(last_emission) } else { None }
+    } // The time the source emitted during the time step ending at the given time, if it did. Emissions at t = 0.0 are launched with the initial rays.
+
+    fn create_rays(&mut self, initial_rays: &mut Rays, source_index: usize, time: f64) {
+        let location = self.position(time);
         let mut initial_angles: Vec<f64> = Vec::with_capacity(self.number_of_rays);
         match self.source_type {
             SourceType::Point => {
@@ -319,16 +889,20 @@ impl Source {
                     initial_angles.push( ray_angle );
                 } // Evenly spaces out the arrays at the source between the given bounds and appends to the initial ray angles struct.
 
-                let local_ray_intensity: f64 = self.intensity / f64::powi(self.number_of_rays as f64, 2);
+                let frequencies: Arc<[f64]> = Arc::from(self.spectrum.frequencies());
 
                 initial_rays.create_rays(initial_angles,
-                    vec![self.location[0] ; self.number_of_rays],
-                    vec![-1.0 * self.location[1];self.number_of_rays],
-                    vec![local_ray_intensity;self.number_of_rays],
-                    vec![self.frequency;self.number_of_rays],
+                    vec![location[0] ; self.number_of_rays],
+                    vec![-1.0 * location[1];self.number_of_rays],
+                    vec![self.intensity;self.number_of_rays],
+                    vec![frequencies;self.number_of_rays],
+                    vec![self.spectrum.levels().to_vec();self.number_of_rays],
                     vec![1.0;self.number_of_rays],
-                    vec![0.0 ; self.number_of_rays])
-            }
+                    vec![0.0 ; self.number_of_rays],
+                    vec![source_index;self.number_of_rays],
+                    vec![time;self.number_of_rays],
+                    vec![angle_spacing;self.number_of_rays])
+            } // Every ray carries the intensity of the source at 1 m, and stands for the rays either side of it up to the angle spacing.
             SourceType::Line => {
                 println!("Not yet implemented");
             }
@@ -337,6 +911,21 @@ impl Source {
 
 }
 
+//                                                  MARK: Step Context Struct
+#[derive(Clone, Copy)]
+struct StepContext<'a> {
+    boundaries: &'a [Boundary],
+    index: &'a BoundaryIndex,
+    surface: &'a SeaSurface,
+    water: &'a WaterColumn,
+    x_limit: [f64;2],
+    y_limit: [f64;2],
+    init_max_intensity: f64,
+    branching: &'a BranchControl,
+    refinement: Option<(f64, usize)>,
+    threads: usize,
+} // The environment the rays are stepped through, which stays the same for the whole of a step.
+
 //                                                  MARK: Rays Struct
 #[derive(Default)]
 pub struct Rays {
@@ -346,10 +935,27 @@ pub struct Rays {
     initial_intensity: Vec<f64>,
     intensity: Vec<f64>,
     step_vector: Vec<f64>,
-    frequency: Vec<f64>,
+    frequencies: Vec<Arc<[f64]>>,
+    spectral_weight: Vec<Vec<f64>>,
+    spectral_intensity: Vec<Vec<f64>>,
+    phase_shift: Vec<f64>,
     propagation_time: Vec<f64>,
     total_distance: Vec<f64>,
-} // Defines the properties of each ray.
+    source: Vec<usize>,
+    emission_time: Vec<f64>,
+    doppler: Vec<f64>,
+    angular_spacing: Vec<f64>,
+    split_depth: Vec<usize>,
+    refinement_depth: Vec<usize>,
+    launch_angle: Vec<f64>,
+    id: Vec<usize>,
+    parent: Vec<Option<usize>>,
+    surface_bounces: Vec<u32>,
+    bottom_bounces: Vec<u32>,
+    next_id: usize,
+    created: usize,
+    events: Option<Vec<Event>>,
+} // Defines the properties of each ray. Every ray carries the frequencies of its source, with the intensity at each of them.
 
 impl Rays {
     pub fn initialise(number_of_rays: usize) -> Self {
@@ -359,10 +965,27 @@ impl Rays {
             y_pos: Vec::with_capacity(number_of_rays as usize),
             initial_intensity: Vec::with_capacity(number_of_rays as usize),
             intensity: Vec::with_capacity(number_of_rays as usize),
-            frequency: Vec::with_capacity(number_of_rays as usize),
+            frequencies: Vec::with_capacity(number_of_rays as usize),
+            spectral_weight: Vec::with_capacity(number_of_rays as usize),
+            spectral_intensity: Vec::with_capacity(number_of_rays as usize),
+            phase_shift: Vec::with_capacity(number_of_rays as usize),
             step_vector: Vec::with_capacity(number_of_rays as usize),
             propagation_time: Vec::with_capacity(number_of_rays as usize),
             total_distance: Vec::with_capacity(number_of_rays as usize),
+            source: Vec::with_capacity(number_of_rays as usize),
+            emission_time: Vec::with_capacity(number_of_rays as usize),
+            doppler: Vec::with_capacity(number_of_rays as usize),
+            angular_spacing: Vec::with_capacity(number_of_rays as usize),
+            split_depth: Vec::with_capacity(number_of_rays as usize),
+            refinement_depth: Vec::with_capacity(number_of_rays),
+            launch_angle: Vec::with_capacity(number_of_rays as usize),
+            id: Vec::with_capacity(number_of_rays as usize),
+            parent: Vec::with_capacity(number_of_rays as usize),
+            surface_bounces: Vec::with_capacity(number_of_rays as usize),
+            bottom_bounces: Vec::with_capacity(number_of_rays as usize),
+            next_id: 0,
+            created: 0,
+            events: None,
         }
     } // Initialisation function to define the initial size of the fields in Rays.
     
@@ -389,45 +1012,224 @@ impl Rays {
     } // Bounds the initial angle of the ray between +/- pi/2 rads (for maths purposes). Also converts the step to show downwards (-) or upwards (+) motion.
 
     fn create_rays(&mut self, angle: Vec<f64>, x_pos: Vec<f64>, y_pos: Vec<f64>,
-         intensity: Vec<f64>, frequency: Vec<f64>, step_vector: Vec<f64> , total_distance: Vec<f64>) -> () {
+         intensity: Vec<f64>, frequencies: Vec<Arc<[f64]>>, spectral_weight: Vec<Vec<f64>>, step_vector: Vec<f64> , total_distance: Vec<f64>, source: Vec<usize>, emission_time: Vec<f64>, angular_spacing: Vec<f64>) {
             self.angle.extend(&angle);
             self.x_pos.extend(x_pos);
             self.y_pos.extend(y_pos);
             self.initial_intensity.extend(intensity.clone());
-            self.intensity.extend(intensity);
-            self.frequency.extend(frequency);
+            for (ray_intensity, weights) in intensity.iter().zip(&spectral_weight) {
+                self.spectral_intensity.push(weights.iter().map(|weight| ray_intensity * weight).collect());
+                self.intensity.push(ray_intensity * weights.iter().sum::<f64>());
+            }
+            self.frequencies.extend(frequencies);
+            self.spectral_weight.extend(spectral_weight);
             self.step_vector.extend(step_vector);
+            self.phase_shift.extend( vec![0.0;angle.len()] );
             self.propagation_time.extend( vec![0.0;angle.len()] );
             self.total_distance.extend(total_distance);
+            self.source.extend(source);
+            self.emission_time.extend(emission_time);
+            self.doppler.extend( vec![1.0;angle.len()] );
+            self.angular_spacing.extend(angular_spacing);
+            self.split_depth.extend( vec![0;angle.len()] );
+            self.refinement_depth.extend( vec![0;angle.len()] );
+            self.id.extend(self.next_id..self.next_id + angle.len());
+            self.parent.extend( vec![None;angle.len()] );
+            self.surface_bounces.extend( vec![0;angle.len()] );
+            self.bottom_bounces.extend( vec![0;angle.len()] );
+            self.next_id += angle.len();
+            self.launch_angle.extend(angle);
     } // Appends data of new rays to the vector fields under Rays.
 
-    fn step<F: SingleInputFunction>(&mut self, dt: f64, boundaries: &mut Vec<Boundary<F>>, simulation_x_limit: [f64;2], simulation_y_limit: [f64;2], init_max_intensity: f64) -> () {
+    fn branch_ray(&mut self, parent: usize, angle: f64, step_vector: f64, intensity: f64, spectral_weight: Vec<f64>) -> usize {
+        self.angle.push(angle);
+        self.x_pos.push(self.x_pos[parent]);
+        self.y_pos.push(self.y_pos[parent]);
+        self.initial_intensity.push(intensity);
+        self.spectral_intensity.push(spectral_weight.iter().map(|weight| intensity * weight).collect());
+        self.intensity.push(intensity * spectral_weight.iter().sum::<f64>());
+        self.step_vector.push(step_vector);
+        self.frequencies.push(self.frequencies[parent].clone());
+        self.spectral_weight.push(spectral_weight);
+        self.phase_shift.push(self.phase_shift[parent]);
+        self.propagation_time.push(self.propagation_time[parent]);
+        self.total_distance.push(self.total_distance[parent]);
+        self.source.push(self.source[parent]);
+        self.emission_time.push(self.emission_time[parent]);
+        self.doppler.push(self.doppler[parent]);
+        self.angular_spacing.push(self.angular_spacing[parent]);
+        self.split_depth.push(self.split_depth[parent] + 1);
+        self.refinement_depth.push(self.refinement_depth[parent]);
+        self.launch_angle.push(self.launch_angle[parent]);
+        self.id.push(PROVISIONAL + self.created);
+        self.parent.push(Some(self.id[parent]));
+        self.surface_bounces.push(self.surface_bounces[parent]);
+        self.bottom_bounces.push(self.bottom_bounces[parent]);
+        self.created += 1;
+        self.x_pos.len() - 1
+    } // Creates a new ray from the position of an existing one, carrying on its travel time and phase, one split deeper. Returns the index of the new ray.
+
+    fn refine(&mut self, separation: f64, max_depth: usize) {
+        let wavefront = |i: usize| (self.source[i], self.emission_time[i].to_bits(), self.split_depth[i]);
+        let mut order: Vec<usize> = (0..self.x_pos.len()).collect();
+        order.sort_by(|a, b| wavefront(*a).cmp(&wavefront(*b)).then(self.launch_angle[*a].total_cmp(&self.launch_angle[*b])));
+        // Puts the rays on each wavefront (rays launched together by the same source, which have split the same number of times) in the order they were launched.
+
+        let mut pairs: Vec<(usize, usize)> = Vec::new();
+        for pair in order.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let neighbours = wavefront(a) == wavefront(b)
+                && self.launch_angle[b] - self.launch_angle[a] <= 0.75 * (self.angular_spacing[a] + self.angular_spacing[b])
+                && self.step_vector[a] * self.step_vector[b] * (self.angle[a] - self.angle[b]).cos() > 0.0;
+            // Rays between them which have been removed, or rays heading in opposite directions, leave a gap which should not be filled.
+            // Neighbours are launched about the mean of their angles apart, and twice that with a ray removed between them.
+            let distance = ((self.x_pos[a] - self.x_pos[b]).powi(2) + (self.y_pos[a] - self.y_pos[b]).powi(2)).sqrt();
+            let shallow = self.refinement_depth[a].max(self.refinement_depth[b]) < max_depth;
+            // Each new ray is one deeper than the deeper of its neighbours, so a wavefront holds at most 2^max_depth times the rays it was launched with.
+            if neighbours && shallow && distance > separation { pairs.push((a, b)) }
+        }
+
+        for (a, b) in pairs { self.interpolate_ray(a, b) }
+    } // Fills the gaps between neighbouring rays which have spread further apart than the separation.
+
+    fn interpolate_ray(&mut self, a: usize, b: usize) {
+        let mean = |values: &Vec<f64>| (values[a] + values[b]) / 2.0;
+        let direction = [self.step_vector[a] * self.angle[a].sin() + self.step_vector[b] * self.angle[b].sin(),
+            self.step_vector[a] * self.angle[a].cos() + self.step_vector[b] * self.angle[b].cos()];
+        let step_vector: f64 = if direction[1] >= 0.0 { 1.0 } else { -1.0 };
+
+        self.angle.push((step_vector * direction[0]).atan2(step_vector * direction[1]));
+        self.x_pos.push(mean(&self.x_pos));
+        self.y_pos.push(mean(&self.y_pos));
+        self.initial_intensity.push(mean(&self.initial_intensity));
+        self.intensity.push(mean(&self.intensity));
+        self.step_vector.push(step_vector);
+        self.frequencies.push(self.frequencies[a].clone());
+        self.spectral_weight.push(self.spectral_weight[a].iter().zip(&self.spectral_weight[b]).map(|(a, b)| (a + b) / 2.0).collect());
+        self.spectral_intensity.push(self.spectral_intensity[a].iter().zip(&self.spectral_intensity[b]).map(|(a, b)| (a + b) / 2.0).collect());
+        self.phase_shift.push(mean(&self.phase_shift));
+        self.propagation_time.push(mean(&self.propagation_time));
+        self.total_distance.push(mean(&self.total_distance));
+        self.source.push(self.source[a]);
+        self.emission_time.push(self.emission_time[a]);
+        self.doppler.push(mean(&self.doppler));
+        self.angular_spacing.push((self.angular_spacing[a] + self.angular_spacing[b]) / 4.0);
+        self.split_depth.push(self.split_depth[a]);
+        self.refinement_depth.push(self.refinement_depth[a].max(self.refinement_depth[b]) + 1);
+        self.launch_angle.push(mean(&self.launch_angle));
+        self.id.push(self.next_id);
+        self.parent.push(None);
+        self.surface_bounces.push(self.surface_bounces[a]);
+        self.bottom_bounces.push(self.bottom_bounces[a]);
+        self.next_id += 1;
+
+        self.angular_spacing[a] *= 0.75;
+        self.angular_spacing[b] *= 0.75;
+        // The new ray takes a quarter of the angle each neighbour stands for, so the total width of the wavefront, and with it the pressure added to the grid, stays the same.
+    } // Adds a ray half way between two neighbouring rays, with the average of their direction, intensity and phase.
+
+    fn step(&mut self, dt: f64, time: f64, context: &StepContext, receivers: &mut [Receiver]) {
+        let detectors: &[Receiver] = receivers;
+        let number_of_rays = self.x_pos.len();
+        let mut parts: Vec<Rays> = Vec::new();
+        for range in parallel::chunks(self.x_pos.len()).into_iter().rev() {
+            parts.push(self.split_off(range.start));
+        }
+        parts.reverse();
+        // Moves each chunk of rays into a set of its own.
+
+        let mut chunks = parallel::map(parts, context.threads, |mut chunk| {
+            let length = chunk.x_pos.len();
+            let (keep, arrivals) = chunk.step_chunk(dt, time, context, detectors);
+            (chunk, length, keep, arrivals)
+        }); // Each chunk of rays is stepped on its own thread, along with any rays they create.
+
+        let mut next_id = self.next_id;
+        for (chunk, _, _, _) in chunks.iter_mut() {
+            next_id = chunk.number_rays(next_id);
+        } // Numbers the rays created during this step in order, so the numbers do not depend on the number of threads.
+
+        let mut rays = Rays::initialise(number_of_rays);
+        rays.next_id = next_id;
+        rays.events = self.events.take();
+        let mut created = Vec::with_capacity(chunks.len());
+        for (mut chunk, length, keep, arrivals) in chunks {
+            if let (Some(events), Some(chunk_events)) = (rays.events.as_mut(), chunk.events.take()) { events.extend(chunk_events) }
+            rays.take_rays(&mut chunk, &keep[..length]);
+            for (r, arrival) in arrivals { receivers[r].add_arrival(arrival) }
+            created.push((chunk, length, keep));
+        }
+        for (mut chunk, length, keep) in created {
+            rays.take_rays(&mut chunk, &keep[length..]);
+        } // The rays which are kept stay in order, followed by the rays created during this step.
+
+        if let Some((separation, max_depth)) = context.refinement { rays.refine(separation, max_depth) }
+
+        if let Some(probabilities) = context.branching.keep_probabilities(&rays.intensity) {
+            let keep: Vec<bool> = probabilities.iter().enumerate().map(|(i, probability)| {
+                let kept = branching::draw(&[time, rays.x_pos[i], rays.y_pos[i], rays.angle[i]]) < *probability;
+                if kept { rays.scale_intensity(i, 1.0 / probability) }
+                else { rays.log(i, time, Interaction::Termination(Termination::Resampled), None, None, None, None) }
+                kept
+            }).collect();
+            let mut resampled = Rays::initialise(probabilities.len());
+            resampled.take_rays(&mut rays, &keep);
+            resampled.next_id = rays.next_id;
+            resampled.events = rays.events.take();
+            rays = resampled;
+        } // Cuts the rays down to the budget, favouring the strongest. The rays which are kept make up for the energy of those which are not.
+        *self = rays;
+    } // Moves every ray forward by one time step, removing those which have left the simulation or faded out.
+
+    fn step_chunk(&mut self, dt: f64, time: f64, context: &StepContext, receivers: &[Receiver]) -> (Vec<bool>, Vec<(usize, Arrival)>) {
+        let &StepContext { boundaries, index, surface, water, x_limit: simulation_x_limit, y_limit: simulation_y_limit, init_max_intensity, branching, .. } = context;
         let mut new_x_pos: f64;
         let mut new_y_pos: f64;
         let mut i: usize = 0;
 
+        let stepped = self.x_pos.len();
+        let mut keep: Vec<bool> = Vec::with_capacity(self.x_pos.len());
+        let mut arrivals: Vec<(usize, Arrival)> = Vec::new();
+
         while i != self.x_pos.len() {
-            // Removes data if it leaves the simulation range
-            if (self.x_pos[i] < simulation_x_limit[0]) || (self.x_pos[i] > simulation_x_limit[1]) || (-self.y_pos[i] < simulation_y_limit[0]) ||
-                 (-self.y_pos[i] > simulation_y_limit[1]) || (self.intensity[i].is_finite() == false) || (self.intensity[i] < init_max_intensity / 10000000000.0) {
-                self.angle.remove(i);
-                self.x_pos.remove(i);
-                self.y_pos.remove(i);
-                self.initial_intensity.remove(i);
-                self.intensity.remove(i);
-                self.step_vector.remove(i);
-                self.frequency.remove(i);
-                self.propagation_time.remove(i);
-                self.total_distance.remove(i);
+            // Marks the ray for removal if it leaves the simulation range
+            let termination = if (self.x_pos[i] < simulation_x_limit[0]) || (self.x_pos[i] > simulation_x_limit[1]) || (-self.y_pos[i] < simulation_y_limit[0]) ||
+                 (-self.y_pos[i] > simulation_y_limit[1]) { Some(Termination::LeftSimulation) }
+                else if self.intensity[i].is_finite() == false { Some(Termination::NotFinite) }
+                else if self.intensity[i] < init_max_intensity / 10000000000.0 { Some(Termination::FadedOut) }
+                else { None };
+
+            if let Some(reason) = termination {
+                self.log(i, time - dt, Interaction::Termination(reason), None, None, None, None);
+                keep.push(false);
+                i += 1;
             } else { 
-                let (old_ray_speed, old_boundary) = self.ray_speed(self.x_pos[i],self.y_pos[i], boundaries);
+                keep.push(true);
+                let (old_ray_speed, old_index) = self.ray_speed(self.x_pos[i],self.y_pos[i], boundaries, index, surface, water, time);
+                let old_boundary = old_index.map(|b| &boundaries[b]);
+
+                let old_current = match old_boundary {
+                    None => water.current_velocity(self.x_pos[i], self.y_pos[i] + surface.height(self.x_pos[i], time)),
+                    Some(_) => 0.0,
+                }; // Only the water itself flows.
 
                 // Caluclates the new position of each ray after 1 time step
                 self.propagation_time[i] += dt;
-                new_x_pos = self.x_pos[i] + self.step_vector[i] * dt * old_ray_speed * self.angle[i].sin();
+                new_x_pos = self.x_pos[i] + self.step_vector[i] * dt * old_ray_speed * self.angle[i].sin() + dt * old_current;
+                // The ray is advected by the current as well as travelling at the speed of sound.
                 new_y_pos = self.y_pos[i] + self.step_vector[i] * dt * old_ray_speed * self.angle[i].cos();
 
-                let (new_ray_speed, new_boundary) = self.ray_speed(new_x_pos, new_y_pos, boundaries);
+                let (new_ray_speed, new_index) = self.ray_speed(new_x_pos, new_y_pos, boundaries, index, surface, water, time);
+                let new_boundary = new_index.map(|b| &boundaries[b]);
+
+                let layer = [surface_layer(self.x_pos[i], self.y_pos[i], surface, time),
+                    surface_layer(new_x_pos, new_y_pos, surface, time)];
+                // Checks which side of the sea surface (or ice) the ray is on before and after the step.
+                let in_open_water = new_boundary.is_none() && layer[1] == Layer::Water;
+                let new_current = match in_open_water {
+                    true => water.current_velocity(new_x_pos, new_y_pos + surface.height(new_x_pos, time)),
+                    false => 0.0,
+                };
 
                 let material_change_test = match (&old_boundary, &new_boundary) {
                     (Some(location_1), Some(location_2)) => {
@@ -436,32 +1238,81 @@ impl Rays {
                     }
                     (Some(_), None) => 2,
                     (None, Some(_)) => 1,
-                    _ => 0
+                    (None, None) => {
+                        if layer[0] != layer[1] { 3 }
+                        else { 0 }
+                    }
                 };
 
+                let (interface, material) = match material_change_test {
+                    0 => (None, None),
+                    1 => (Some(Interface::Boundary(new_index.unwrap())), Some(new_boundary.unwrap().material.material_type())),
+                    2 => (Some(Interface::Boundary(old_index.unwrap())), Some(old_boundary.unwrap().material.material_type())),
+                    _ => (Some(Interface::Surface), surface.ice_cover().filter(|_| layer.contains(&Layer::Ice)).map(|ice| ice.material().material_type())),
+                }; // The interface being crossed, and the material of the boundary or ice there.
+                let seabed = old_boundary.is_none() != new_boundary.is_none();
+                // Only boundaries with water on one side are the seabed. Interfaces between layers below it are not bottom bounces.
+                let totally_reflected = new_ray_speed > old_ray_speed && self.angle[i].abs() > (old_ray_speed / new_ray_speed).asin();
+                let mut incidence: Option<f64> = None;
+
                 if material_change_test != 0 {
-                    let r_coeff: f64;
-                    let t_coeff: f64;
-                    if material_change_test == 1 { 
-                        self.reflection(new_boundary.clone().unwrap(), new_x_pos, new_y_pos, i); 
-                        (r_coeff, t_coeff) = self.reflection_and_transmission(old_boundary, new_boundary,
-                        old_ray_speed, new_ray_speed, i);
-                    } else { 
-                        self.reflection(old_boundary.clone().unwrap(), new_x_pos, new_y_pos, i);
-                        (r_coeff, t_coeff) = self.reflection_and_transmission(old_boundary, new_boundary,
-                        old_ray_speed, new_ray_speed, i);
+                    let slope = match material_change_test {
+                        1 => new_boundary.unwrap().differentiate(new_x_pos),
+                        2 => old_boundary.unwrap().differentiate(new_x_pos),
+                        _ => surface.slope(new_x_pos, time),
+                    }; // Surface reflections use the instantaneous slope of the sea surface.
+                    incidence = self.incidence_angle(i, slope);
+
+                    let roughness = match material_change_test {
+                        1 => new_boundary.unwrap().roughness,
+                        2 => old_boundary.unwrap().roughness,
+                        _ => match surface.ice_cover() {
+                            Some(ice) if layer.contains(&Layer::Water) => ice.roughness(),
+                            Some(_) => None,
+                            None => surface.roughness(),
+                        },
+                    }; // Rays reaching the surface from below see the underside of the ice, if there is any.
+
+                    let (r_coeff, t_coeff, r_phase) = self.reflection_and_transmission(old_boundary, new_boundary,
+                        layer, surface.ice_cover(), old_ray_speed, new_ray_speed, i);
+                    let branch = match i < stepped {
+                        true => branching.branch(self.split_depth[i], self.intensity[i] * r_coeff, init_max_intensity, &[time, self.x_pos[i], self.y_pos[i], self.angle[i]]),
+                        false => None,
+                    }; // Rays created during this step do not split again until the next one, so a ray cannot keep splitting at the same point.
+
+                    if let Some(factor) = branch {
+                        if let Some(reflected_index) = self.reflection(slope, new_x_pos, new_y_pos, i) {
+                            self.scale_intensity(reflected_index, r_coeff * factor);
+                            self.phase_shift[reflected_index] += r_phase;
+                            match interface {
+                                Some(Interface::Surface) => self.surface_bounces[reflected_index] += 1,
+                                _ if seabed => self.bottom_bounces[reflected_index] += 1,
+                                _ => (),
+                            }
+                            self.log(reflected_index, time, Interaction::Reflection, interface, material, incidence, Some([r_coeff, t_coeff, r_phase]));
+                            if let Some(roughness) = roughness {
+                                self.rough_reflection(roughness, slope, i, reflected_index, old_ray_speed, branching, init_max_intensity, time);
+                            } // Removes the energy scattered by a rough interface from the specular reflection.
+                        }
                     }
-                    *self.intensity.last_mut().unwrap() *= r_coeff;
-                    self.intensity[i] *= t_coeff;
+                    self.scale_intensity(i, t_coeff);
+                    if !totally_reflected {
+                        self.log(i, time, Interaction::Transmission, interface, material, incidence, Some([r_coeff, t_coeff, r_phase]));
+                    } // Rays turned back below are logged as a total internal reflection instead.
                 }
 
                 
-                if new_ray_speed > old_ray_speed {
-                    let critical_angle : f64 = (old_ray_speed/new_ray_speed).asin();
+                if totally_reflected {
                     // Reflects the ray if its angle with the normal exceeds the critical angle.
-                    if self.angle[i].abs() > critical_angle.abs() {
-                        self.angle[i] = -1.0 * self.angle[i];
-                        self.step_vector[i] = self.step_vector[i] * -1.0;
+                    self.angle[i] = -1.0 * self.angle[i];
+                    self.step_vector[i] = self.step_vector[i] * -1.0;
+                    match interface {
+                        Some(Interface::Surface) => self.surface_bounces[i] += 1,
+                        Some(Interface::Boundary(_)) if seabed => self.bottom_bounces[i] += 1,
+                        _ => (),
+                    } // Turning points within the water column are not bounces.
+                    if interface.is_some() {
+                        self.log(i, time, Interaction::TotalInternalReflection, interface, material, incidence, Some([1.0, 0.0, 0.0]));
                     }
                 }
 
@@ -469,100 +1320,218 @@ impl Rays {
                 let preangle = new_ray_speed / old_ray_speed * self.angle[i].sin();
 
 
-                let distance_travelled = ((new_x_pos*new_x_pos + new_y_pos*new_y_pos).sqrt() - (self.x_pos[i]*self.x_pos[i] + self.y_pos[i]*self.y_pos[i]).sqrt()).abs();
+                let distance_travelled = ((new_x_pos - self.x_pos[i]).powi(2) + (new_y_pos - self.y_pos[i]).powi(2)).sqrt();
                 self.total_distance[i] += distance_travelled;
 
 
 
+                for (r, receiver) in receivers.iter().enumerate() {
+                    let arrival = Arrival {
+                        source: self.source[i],
+                        emission_time: self.emission_time[i],
+                        delay: self.propagation_time[i] - dt,
+                        amplitude: self.intensity[i].sqrt(),
+                        phase: self.phase_shift[i],
+                        doppler: self.doppler[i],
+                        surface_bounces: self.surface_bounces[i],
+                        bottom_bounces: self.bottom_bounces[i],
+                    };
+                    if let Some(arrival) = receiver.detect([[self.x_pos[i], -self.y_pos[i]], [new_x_pos, -new_y_pos]], [time - dt, time], old_ray_speed, self.tube_width(i), arrival) {
+                        arrivals.push((r, arrival));
+                    }
+                } // Receivers pick up the rays which pass them during this step.
+
                 self.x_pos[i] = new_x_pos;
                 self.y_pos[i] = new_y_pos;
-                self.angle[i] = ( new_ray_speed / old_ray_speed * self.angle[i].sin() ).asin();
+                (self.angle[i], self.step_vector[i]) = moving_medium_refraction(self.angle[i], self.step_vector[i], [old_ray_speed, new_ray_speed], [old_current, new_current]);
+
+                if let (Layer::Ice, Some(ice)) = (layer[1], surface.ice_cover()) {
+                    self.scale_spectrum(i, |frequency| ice.attenuation(frequency, new_ray_speed, dt * old_ray_speed));
+                } // Absorption inside of the ice layer.
+
+                if in_open_water {
+                    let depth = new_y_pos + surface.height(new_x_pos, time);
+                    self.scale_spectrum(i, |frequency| water.excess_loss(frequency, new_ray_speed, depth, dt * old_ray_speed));
+                } // Extra losses in the water, such as from a bubble layer.
 
                 let salinity = 35.0;
 
                 let temperature = self.temperature_at_depth(self.y_pos[i]);
-                //self.intensity[i] *= 1.0 - self.calculate_absorption(self.frequency[i], temperature, salinity, self.y_pos[i]);
+                let geometric_intensity = self.initial_intensity[i] * REFERENCE_DISTANCE / self.total_distance[i].max(REFERENCE_DISTANCE);
+                // Cylindrical spreading, as the simulation is two dimensional, from the source level at 1 m.
 
-                self.intensity[i] = (1.0 - self.calculate_absorption(self.frequency[i], temperature, salinity, self.y_pos[i])) * self.initial_intensity[i] * (1.0/(PI * 2.0 * self.total_distance[i]));
+                self.intensity[i] = 0.0;
+                for k in 0..self.frequencies[i].len() {
+                    let absorption = self.calculate_absorption(self.frequencies[i][k], temperature, salinity, self.y_pos[i]);
+                    self.spectral_intensity[i][k] = (1.0 - absorption) * self.spectral_weight[i][k] * geometric_intensity;
+                    self.intensity[i] += self.spectral_intensity[i][k];
+                } // The geometry is shared by every frequency, only the absorption differs between them.
 
                 i += 1;
             }
         }
-    }
 
-    fn output_phase(&self, index: usize) -> f64 {
-        2.0 * PI * self.frequency[index] * self.propagation_time[index]
-    }
+        (keep, arrivals)
+    } // Steps a set of rays, including any they create. Returns which rays to keep and the arrivals at each receiver, in order.
+
+    fn split_off(&mut self, at: usize) -> Rays {
+        Rays {
+            angle: self.angle.split_off(at),
+            x_pos: self.x_pos.split_off(at),
+            y_pos: self.y_pos.split_off(at),
+            initial_intensity: self.initial_intensity.split_off(at),
+            intensity: self.intensity.split_off(at),
+            step_vector: self.step_vector.split_off(at),
+            frequencies: self.frequencies.split_off(at),
+            spectral_weight: self.spectral_weight.split_off(at),
+            spectral_intensity: self.spectral_intensity.split_off(at),
+            phase_shift: self.phase_shift.split_off(at),
+            propagation_time: self.propagation_time.split_off(at),
+            total_distance: self.total_distance.split_off(at),
+            source: self.source.split_off(at),
+            emission_time: self.emission_time.split_off(at),
+            doppler: self.doppler.split_off(at),
+            angular_spacing: self.angular_spacing.split_off(at),
+            split_depth: self.split_depth.split_off(at),
+            refinement_depth: self.refinement_depth.split_off(at),
+            launch_angle: self.launch_angle.split_off(at),
+            id: self.id.split_off(at),
+            parent: self.parent.split_off(at),
+            surface_bounces: self.surface_bounces.split_off(at),
+            bottom_bounces: self.bottom_bounces.split_off(at),
+            next_id: self.next_id,
+            created: 0,
+            events: self.events.as_ref().map(|_| Vec::new()),
+        }
+    } // Moves the rays from the index onwards into a set of their own.
 
-    fn ray_speed<F: SingleInputFunction>(&mut self, x_pos: f64, y_pos: f64, boundaries: &mut Vec<Boundary<F>>) -> (f64, Option<Boundary<F>>) {
-        let mut current_boundary: Option<usize> = None;
-        let mut boundary_height: Option<f64> = None;
-        let velocity_air: f64 = 343.0; // m s^-1
-        let ycase: u32;
-
-        // Filters out any None variables
-        let mut valid_boundaries: Vec<_> = boundaries
-            .iter_mut()
-            .filter(|b| {
-                if let Some(height) = b.boundary_height(x_pos) {
-                    // Filter out NaN and infinite values
-                    height.is_finite()
-                } else {
-                    // If the height is None, consider it invalid
-                    false
-                }
-            })
-            .collect();
+    fn take_rays(&mut self, other: &mut Rays, keep: &[bool]) {
+        fn take<T>(values: &mut Vec<T>, other: &mut Vec<T>, keep: &[bool]) {
+            values.extend(other.drain(..keep.len()).zip(keep).filter_map(|(value, keep)| keep.then_some(value)));
+        }
 
-        // For the cases where the is a boundary at some y position at the given x position
-        if !valid_boundaries.is_empty() {
-            
-            // Sorts valid_boundaries in order of magnitude of the output of boundary_height.unwrap()
-            valid_boundaries.sort_by(|a, b| {
-                let a_height = a.boundary_height(x_pos).unwrap_or_default();
-                let b_height = b.boundary_height(x_pos).unwrap_or_default();
-                a_height.partial_cmp(&b_height).unwrap()
+        take(&mut self.angle, &mut other.angle, keep);
+        take(&mut self.x_pos, &mut other.x_pos, keep);
+        take(&mut self.y_pos, &mut other.y_pos, keep);
+        take(&mut self.initial_intensity, &mut other.initial_intensity, keep);
+        take(&mut self.intensity, &mut other.intensity, keep);
+        take(&mut self.step_vector, &mut other.step_vector, keep);
+        take(&mut self.frequencies, &mut other.frequencies, keep);
+        take(&mut self.spectral_weight, &mut other.spectral_weight, keep);
+        take(&mut self.spectral_intensity, &mut other.spectral_intensity, keep);
+        take(&mut self.phase_shift, &mut other.phase_shift, keep);
+        take(&mut self.propagation_time, &mut other.propagation_time, keep);
+        take(&mut self.total_distance, &mut other.total_distance, keep);
+        take(&mut self.source, &mut other.source, keep);
+        take(&mut self.emission_time, &mut other.emission_time, keep);
+        take(&mut self.doppler, &mut other.doppler, keep);
+        take(&mut self.angular_spacing, &mut other.angular_spacing, keep);
+        take(&mut self.split_depth, &mut other.split_depth, keep);
+        take(&mut self.refinement_depth, &mut other.refinement_depth, keep);
+        take(&mut self.launch_angle, &mut other.launch_angle, keep);
+        take(&mut self.id, &mut other.id, keep);
+        take(&mut self.parent, &mut other.parent, keep);
+        take(&mut self.surface_bounces, &mut other.surface_bounces, keep);
+        take(&mut self.bottom_bounces, &mut other.bottom_bounces, keep);
+    } // Moves the first rays of another set onto the end of this one, given whether each of them is kept, leaving the rest.
+
+    fn number_rays(&mut self, first_id: usize) -> usize {
+        let number = |id: &mut usize| if *id >= PROVISIONAL { *id = first_id + (*id - PROVISIONAL) };
+        self.id.iter_mut().for_each(number);
+        self.parent.iter_mut().flatten().for_each(number);
+        if let Some(events) = self.events.as_mut() { events.iter_mut().for_each(|event| number(&mut event.ray)) }
+        first_id + self.created
+    } // Gives the rays created during a step their ids, starting from the first id given. Returns the id after the last one used.
+
+    fn log(&mut self, index: usize, time: f64, interaction: Interaction, interface: Option<Interface>, material: Option<MaterialType>, incidence: Option<f64>, coefficients: Option<[f64;3]>) {
+        let position = [self.x_pos[index], -self.y_pos[index]];
+        if let Some(events) = self.events.as_mut() {
+            events.push(Event {
+                ray: self.id[index],
+                time,
+                position,
+                interaction,
+                interface,
+                material,
+                incidence,
+                coefficients,
             });
+        }
+    } // Adds an interaction of a ray to the event log, if interactions are being logged.
+
+    fn record_paths(&self, paths: &mut RayPaths, time: f64) {
+        for i in 0..self.x_pos.len() {
+            paths.record(PathPoint {
+                ray: self.id[i],
+                parent: self.parent[i],
+                source: self.source[i],
+                time,
+                x: self.x_pos[i],
+                y: -self.y_pos[i],
+                intensity: self.intensity[i],
+            });
+        }
+    } // Adds the current position of every ray, with y upwards, to the recorded paths.
+
+    fn scale_intensity(&mut self, index: usize, factor: f64) {
+        self.initial_intensity[index] *= factor;
+        self.intensity[index] *= factor;
+        for intensity in self.spectral_intensity[index].iter_mut() { *intensity *= factor }
+    } // Applies a loss to a ray which carries through to every following step.
+
+    fn scale_spectrum<F: Fn(f64) -> f64>(&mut self, index: usize, loss: F) {
+        self.intensity[index] = 0.0;
+        for k in 0..self.frequencies[index].len() {
+            let factor = loss(self.frequencies[index][k]);
+            self.spectral_weight[index][k] *= factor;
+            self.spectral_intensity[index][k] *= factor;
+            self.intensity[index] += self.spectral_intensity[index][k];
+        }
+    } // Applies a frequency dependent loss to a ray, given the fraction of energy kept at each frequency.
 
-            // Checks if the ray is inside any boundary
-            if -y_pos < valid_boundaries[valid_boundaries.len()-1].boundary_height(x_pos).unwrap() {
-                ycase = 3; // Boundary
-
-                // Determines the boundary that the ray is in
-                for i in 0..valid_boundaries.len() {
-                    if let Some(height) = valid_boundaries[i].boundary_height(x_pos) {
-                        if height > -y_pos && i != valid_boundaries.len() {
-                            current_boundary = Some(i);
-                            boundary_height = Some(height);
-                            break;
-                        } else if i == valid_boundaries.len() { 
-                            current_boundary = Some(valid_boundaries.len());
-                            boundary_height = valid_boundaries[current_boundary.unwrap()].boundary_height(x_pos);
-                        }
-                    }
-                }
-            } 
-            else if -y_pos > 0.0 { ycase = 2; } // Air
-            else { ycase = 1; } // Water
+    fn tube_width(&self, index: usize) -> f64 {
+        self.total_distance[index].max(REFERENCE_DISTANCE) * self.angular_spacing[index]
+    } // The width of the wavefront a ray stands for, which grows as it spreads out from the source.
 
-        } else {
-            if -y_pos > 0.0 { ycase = 2; } // Air
-            else { ycase = 1; } // Water
-        }
+    fn sampling_weight(&self, index: usize, size: f64) -> f64 {
+        (self.tube_width(index) / size).min(1.0).powi(2)
+    } // Scales the intensity of a ray so that the rays of a wavefront crossing a region of the given size add up to the intensity of the wavefront, however many rays there are.
 
-        match ycase{
-            1=>(self.velocity_water(-y_pos), None),
-            2=>(velocity_air, None),
-            _=>(valid_boundaries[current_boundary.unwrap()].material.calculate_velocity(-y_pos - boundary_height.unwrap()), 
-                Some( valid_boundaries[current_boundary.unwrap()].clone() )),
-        }
+    fn output_phase(&self, index: usize, frequency_index: usize, source_delay: f64) -> f64 {
+        2.0 * PI * self.frequencies[index][frequency_index] * (self.doppler[index] * self.propagation_time[index] + source_delay) + self.phase_shift[index]
+    } // Includes the Doppler shift from a moving source and the phase shifts picked up from reflections, such as the pressure release sea surface.
+    // The signal of a delayed array element lags behind by its delay, so the elements of a steered array add up in phase along the steering direction.
+
+    fn ray_speed(&mut self, x_pos: f64, y_pos: f64, boundaries: &[Boundary], index: &BoundaryIndex, surface: &SeaSurface, water: &WaterColumn, time: f64) -> (f64, Option<usize>) {
+        let velocity_air: f64 = 343.0; // m s^-1
+        let surface_height = surface.height(x_pos, time);
+
+        if let Some((current_boundary, boundary_height)) = index.boundary_above(boundaries, x_pos, -y_pos) {
+            return (boundaries[current_boundary].material.clone().calculate_velocity(-y_pos - boundary_height), Some(current_boundary));
+        } // The ray is inside the lowest boundary whose interface is above it.
+
+        if -y_pos > surface_height {
+            (match surface.ice_cover() {
+                Some(ice) if -y_pos < surface_height + ice.thickness() => ice.material().calculate_velocity(0.0),
+                _ => velocity_air,
+            }, None)
+        } // Air, or the ice floating on the water.
+        else { (water.sound_speed(self.velocity_water(-y_pos), x_pos, y_pos + surface_height), None) } // Water
     }
 
-    fn reflection<F: SingleInputFunction>(&mut self, mut boundary: Boundary<F>, new_x_pos: f64, new_y_pos: f64, ray_index: usize) -> () {
+    fn incidence_angle(&self, ray_index: usize, slope: f64) -> Option<f64> {
+        if slope.is_nan() { return None }
+        let direction = [self.step_vector[ray_index] * self.angle[ray_index].sin(), self.step_vector[ray_index] * self.angle[ray_index].cos()];
+        let normal = if slope.is_infinite() { [1.0, 0.0] }
+            else { [slope / (1.0 + slope.powi(2)).sqrt(), 1.0 / (1.0 + slope.powi(2)).sqrt()] };
+        Some((direction[0] * normal[0] + direction[1] * normal[1]).abs().min(1.0).acos())
+    } // The angle between a ray and the normal to the interface it is crossing (rad).
+
+    fn reflection(&mut self, slope: f64, new_x_pos: f64, new_y_pos: f64, ray_index: usize) -> Option<usize> {
         let delta_x = new_x_pos - self.x_pos[ray_index];
         let delta_y = new_y_pos - self.y_pos[ray_index];
-        let slope = boundary.differentiate(new_x_pos);
-        let normal = -slope.powi(-1);
+        let normal = slope.powi(-1);
+        // Gradient of the normal to the interface, in the same (x, depth) frame as the step.
         let reflected_angle: f64;
         let step_vector: f64;
 
@@ -575,49 +1544,127 @@ impl Rays {
             reflected_angle = self.angle[ray_index];
             step_vector = -1.0 * self.step_vector[ray_index]
         } else {
-            let dot_product = delta_x + delta_y * normal;
+            let dot_product = (delta_x + delta_y * normal) / (1.0 + normal.powi(2));
 
             let new_delta_x = delta_x - 2.0 * dot_product;
             let new_delta_y = delta_y - 2.0 * dot_product * normal;
 
             reflected_angle = ( new_delta_x / new_delta_y ).atan();
-            step_vector = self.step_vector[ray_index]
-        }
+            step_vector = if new_delta_y >= 0.0 { 1.0 } else { -1.0 };
+        } // Mirrors the step in the interface. The reflected ray travels up or down depending on which way it leaves.
 
         if (reflected_angle - slope.atan()).abs() > TOLERANCE {
-            self.create_rays(vec![reflected_angle], vec![self.x_pos[ray_index]], vec![self.y_pos[ray_index]],
-                vec![self.intensity[ray_index]], vec![self.frequency[ray_index]], vec![step_vector],vec![self.total_distance[ray_index]]);
+            let weights = self.spectral_weight[ray_index].clone();
+            let reflected_index = self.branch_ray(ray_index, reflected_angle, step_vector, self.initial_intensity[ray_index], weights);
+            // The reflected ray carries on spreading from the distance the incident ray has already travelled.
 
             self.bound_angles([self.x_pos.len(), self.x_pos.len()]);
+            return Some(reflected_index);
         }
-    }
+        None
+    } // Creates a reflected ray, returning its index, unless the reflection is too shallow to resolve.
+
+    fn rough_reflection(&mut self, roughness: Roughness, slope: f64, ray_index: usize, reflected_index: usize, speed: f64, branching: &BranchControl, max_intensity: f64, time: f64) {
+        if slope.is_nan() { return }
+
+        let incident = [self.step_vector[ray_index] * self.angle[ray_index].sin(), self.step_vector[ray_index] * self.angle[ray_index].cos()];
+        let mut normal = if slope.is_infinite() { [1.0, 0.0] }
+            else { [slope / (1.0 + slope.powi(2)).sqrt(), 1.0 / (1.0 + slope.powi(2)).sqrt()] };
+        // Unit normal to the interface, in the same (x, depth) frame as the ray direction.
+
+        let cos_incidence = incident[0] * normal[0] + incident[1] * normal[1];
+        if cos_incidence > 0.0 { normal = [-normal[0], -normal[1]] }
+        // Points the normal back towards the side of the interface the ray arrived from.
+
+        let coherent_fraction = |frequency: f64| roughness.coherent_fraction(2.0 * PI * frequency / speed, cos_incidence.abs());
+        let diffuse_weights: Vec<f64> = self.frequencies[reflected_index].iter().zip(&self.spectral_weight[reflected_index])
+            .map(|(frequency, weight)| weight * (1.0 - coherent_fraction(*frequency))).collect();
+        // Rougher interfaces, relative to the wavelength, scatter more of the energy incoherently.
+
+        let spreading = self.intensity[ray_index] / (self.initial_intensity[ray_index] * self.spectral_weight[ray_index].iter().sum::<f64>());
+        // The fraction of its intensity at 1 m the incident ray has kept, which the diffuse rays start with too.
+
+        for (direction, weight) in roughness.diffuse_directions(incident, normal) {
+            let step_vector: f64 = if direction[1] >= 0.0 { 1.0 } else { -1.0 };
+            let angle = (step_vector * direction[0]).atan2(step_vector * direction[1]);
+            let intensity = self.initial_intensity[reflected_index] * weight * diffuse_weights.iter().sum::<f64>() * spreading;
+            if let Some(factor) = branching.branch(self.split_depth[reflected_index], intensity, max_intensity, &[time, self.x_pos[ray_index], self.y_pos[ray_index], angle]) {
+                self.branch_ray(reflected_index, angle, step_vector, self.initial_intensity[reflected_index] * weight * factor, diffuse_weights.clone());
+            }
+        } // Creates the diffuse rays from the reflection point, sharing out the incoherently scattered energy.
+
+        self.scale_spectrum(reflected_index, coherent_fraction);
+    } // Splits a specular reflection from a rough interface into the coherent reflection and diffuse rays.
+
+    fn reflection_and_transmission(&mut self, material_1: Option<&Boundary>, material_2: Option<&Boundary>, layer: [Layer;2], ice: Option<&IceCover>, old_speed: f64, new_speed: f64, ray_index: usize) -> (f64, f64, f64) {
+        if let (None, None, [Layer::Water, Layer::Ice], Some(ice)) = (&material_1, &material_2, layer, ice) {
+            return self.elastic_reflection(ice, old_speed, ray_index);
+        } // Rays reaching the ice from the water can also lose energy to shear waves in the ice.
 
-    fn reflection_and_transmission<F: SingleInputFunction>(&mut self, material_1: Option<Boundary<F>>, material_2: Option<Boundary<F>>, old_speed: f64, new_speed: f64, ray_index: usize) -> (f64, f64) {
         let z1: f64;
         let z2: f64;
         if let Some(boundary_1) = material_1 { 
             z1 = self.acoustic_impedance(ImpedenceInput::Material(boundary_1.material),old_speed, boundary_1.boundary_height(self.x_pos[ray_index]), ray_index);
         }
         else {
-            let density: f64;
-            if self.y_pos[ray_index] > 0.0 { density = 1.293 }
-            else { density = self.density_water(ray_index) }
+            let density = self.layer_density(layer[0], ice, ray_index);
             z1 = self.acoustic_impedance(ImpedenceInput::Density(density), old_speed, None, ray_index);
         }
         if let Some(boundary_2) = material_2 { 
             z2 = self.acoustic_impedance(ImpedenceInput::Material(boundary_2.material),new_speed, boundary_2.boundary_height(self.x_pos[ray_index]), ray_index); 
         }
         else {
-            let density: f64;
-            if self.y_pos[ray_index] > 0.0 { density = 1.293 }
-            else { density = self.density_water(ray_index) }
+            let density = self.layer_density(layer[1], ice, ray_index);
             z2 = self.acoustic_impedance(ImpedenceInput::Density(density), new_speed, None, ray_index);
         }
     
         let r_coeff = ((z2 * (self.angle[ray_index]).cos()) - (z1 * (self.angle[ray_index]).cos())) / ((z2 * (self.angle[ray_index]).cos()) + (z1*(self.angle[ray_index]).cos()));
-        let t_coeff = 1.0 - r_coeff;
+        let reflected_fraction = r_coeff.powi(2);
+        let transmitted_fraction = 1.0 - reflected_fraction;
+        let reflected_phase = if r_coeff < 0.0 { PI } else { 0.0 };
     
-        (r_coeff, t_coeff)
+        (reflected_fraction, transmitted_fraction, reflected_phase)
+    } // Returns the fractions of energy reflected and transmitted, and the phase shift of the reflection.
+
+    fn elastic_reflection(&mut self, ice: &IceCover, water_speed: f64, ray_index: usize) -> (f64, f64, f64) {
+        let mut material = ice.material();
+        let ice_density = material.calculate_density(0.0, 0.0);
+        let compressional_speed = material.calculate_velocity(0.0);
+        let shear_speed = material.shear_velocity();
+
+        let sin_water = self.angle[ray_index].sin().abs();
+        let sin_compressional = compressional_speed / water_speed * sin_water;
+        let sin_shear = shear_speed / water_speed * sin_water;
+        if sin_compressional >= 1.0 || sin_water >= 1.0 {
+            return (1.0, 0.0, 0.0);
+        } // All of the energy is reflected beyond the critical angle.
+
+        let cos_water = (1.0 - sin_water.powi(2)).sqrt();
+        let cos_compressional = (1.0 - sin_compressional.powi(2)).sqrt();
+        let cos_shear = (1.0 - sin_shear.powi(2)).sqrt();
+        let cos_two_shear = 1.0 - 2.0 * sin_shear.powi(2);
+        let sin_two_shear = 2.0 * sin_shear * cos_shear;
+
+        let z_water = self.density_water(ray_index) * water_speed / cos_water;
+        let z_compressional = ice_density * compressional_speed / cos_compressional;
+        let z_shear = ice_density * shear_speed / cos_shear;
+        let z_ice = z_compressional * cos_two_shear.powi(2) + z_shear * sin_two_shear.powi(2);
+        // Input impedance of the elastic ice, combining its compressional and shear waves.
+
+        let r_coeff = (z_ice - z_water) / (z_ice + z_water);
+        let reflected_fraction = r_coeff.powi(2);
+        let transmitted_fraction = (1.0 - reflected_fraction) * z_compressional * cos_two_shear.powi(2) / z_ice;
+        let reflected_phase = if r_coeff < 0.0 { PI } else { 0.0 };
+
+        (reflected_fraction, transmitted_fraction, reflected_phase)
+    } // Reflection from a fluid/elastic solid interface. Energy converted into shear waves is lost from the simulation.
+
+    fn layer_density(&mut self, layer: Layer, ice: Option<&IceCover>, ray_index: usize) -> f64 {
+        match (layer, ice) {
+            (Layer::Air, _) => 1.293,
+            (Layer::Ice, Some(ice)) => ice.material().calculate_density(0.0, 0.0),
+            _ => self.density_water(ray_index),
+        }
     }
 
     fn acoustic_impedance(&mut self, input: ImpedenceInput, speed_of_sound: f64, boundary_height: Option<f64>, ray_index: usize) -> f64 {
@@ -692,34 +1739,197 @@ enum ImpedenceInput {
     Density(f64),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Layer {
+    Water,
+    Ice,
+    Air,
+}
+
+fn surface_layer(x_pos: f64, y_pos: f64, surface: &SeaSurface, time: f64) -> Layer {
+    let height_above_surface = -y_pos - surface.height(x_pos, time);
+    if height_above_surface <= 0.0 { return Layer::Water }
+
+    match surface.ice_cover() {
+        Some(ice) if height_above_surface < ice.thickness() => Layer::Ice,
+        _ => Layer::Air,
+    }
+} // Determines whether a position is below the sea surface, inside of the ice cover or in the air.
+
+fn moving_medium_refraction(angle: f64, step_vector: f64, speeds: [f64;2], currents: [f64;2]) -> (f64, f64) {
+    let horizontal_slowness = step_vector * angle.sin() / (speeds[0] + currents[0] * step_vector * angle.sin());
+    let sin_angle = step_vector * horizontal_slowness * speeds[1] / (1.0 - horizontal_slowness * currents[1]);
+    if sin_angle.abs() > 1.0 { return (-angle, -step_vector) }
+    // No direction conserves the slowness, so the ray turns back vertically, as it does at the critical angle.
+
+    (sin_angle.asin(), step_vector)
+} // Snell's law in a moving medium, where sin(angle) / (c + u sin(angle)) is conserved across the step. Returns the new angle and step vector.
+
 //                                                  MARK: Boundary Struct
 
-pub trait SingleInputFunction: Clone {
-    fn evaluate(&self, x: f64) -> f64;
-}
+pub trait BoundaryShape: Send + Sync {
+    fn height(&self, x: f64) -> f64;
+
+    fn slope(&self, _x: f64) -> Option<f64> {
+        None
+    } // Shapes which know their own derivative override this, otherwise the boundary is differentiated numerically.
+
+    fn x_extent(&self) -> Option<[f64;2]> {
+        None
+    } // Shapes which only have a height over part of the x axis return its ends, so the boundary index does not miss them between samples.
+} // Any shape which gives a single height for a given x position can be used as a boundary.
 
-impl<F> SingleInputFunction for F
+impl<F> BoundaryShape for F
 where
-    F: Fn(f64) -> f64 + Clone,
+    F: Fn(f64) -> f64 + Send + Sync,
 {
-    fn evaluate(&self, x: f64) -> f64 {
+    fn height(&self, x: f64) -> f64 {
         self(x)
     }
+} // Allows any closure or function of x, capturing or not, to be used directly as a boundary shape.
+
+pub struct Table {
+    x_values: Vec<f64>,
+    y_values: Vec<f64>,
+}
+
+impl Table {
+    pub fn new(points: Vec<[f64;2]>) -> Self {
+        if points.len() < 2 {
+            eprintln!("Error: A boundary table must contain at least two points.");
+            std::process::exit(1);
+        }
+        if points.windows(2).any(|pair| pair[1][0] <= pair[0][0]) {
+            eprintln!("Error: The x values of a boundary table must be strictly increasing.");
+            std::process::exit(1);
+        }
+
+        Self {
+            x_values: points.iter().map(|point| point[0]).collect(),
+            y_values: points.iter().map(|point| point[1]).collect(),
+        }
+    } // Initialisation function to define the tabulated heights (e.g. measured bathymetry) after undergoing necessary error checks.
+
+    fn segment(&self, x: f64) -> Option<usize> {
+        if x < self.x_values[0] || x > self.x_values[self.x_values.len() - 1] {
+            return None;
+        }
+        let upper = self.x_values.partition_point(|value| *value < x).max(1);
+        Some(upper - 1)
+    } // Returns the index of the first point of the segment containing x, or None if x is outside of the table.
+}
+
+impl BoundaryShape for Table {
+    fn height(&self, x: f64) -> f64 {
+        match self.segment(x) {
+            Some(i) => {
+                let fraction = (x - self.x_values[i]) / (self.x_values[i + 1] - self.x_values[i]);
+                self.y_values[i] + fraction * (self.y_values[i + 1] - self.y_values[i])
+            }
+            None if x < self.x_values[0] => self.y_values[0],
+            None => self.y_values[self.y_values.len() - 1],
+        }
+    } // Linearly interpolates between the tabulated points, holding the end values constant outside of the table.
+
+    fn slope(&self, x: f64) -> Option<f64> {
+        match self.segment(x) {
+            Some(i) => Some((self.y_values[i + 1] - self.y_values[i]) / (self.x_values[i + 1] - self.x_values[i])),
+            None => Some(0.0),
+        }
+    }
+}
+
+pub struct Polygon {
+    vertices: Vec<[f64;2]>,
 }
 
-pub struct Boundary<F: SingleInputFunction> {
-    shape_function : Box<F>,
+impl Polygon {
+    pub fn new(vertices: Vec<[f64;2]>) -> Self {
+        if vertices.len() < 3 {
+            eprintln!("Error: A boundary polygon must contain at least three vertices.");
+            std::process::exit(1);
+        }
+
+        Self {
+            vertices,
+        }
+    } // Initialisation function for a closed polygon (e.g. a seamount or wreck), with the last vertex joining back onto the first.
+
+    fn top_edge(&self, x: f64) -> Option<(f64, f64)> {
+        let mut top: Option<(f64, f64)> = None;
+
+        for i in 0..self.vertices.len() {
+            let [x1, y1] = self.vertices[i];
+            let [x2, y2] = self.vertices[(i + 1) % self.vertices.len()];
+
+            if x1 == x2 || x < x1.min(x2) || x > x1.max(x2) {
+                continue;
+            } // Vertical edges and edges which do not span x cannot be the top of the polygon at x.
+
+            let slope = (y2 - y1) / (x2 - x1);
+            let height = y1 + slope * (x - x1);
+            if top.map_or(true, |(top_height, _)| height > top_height) {
+                top = Some((height, slope));
+            }
+        }
+        top
+    } // Returns the height and slope of the highest edge of the polygon above x, if there is one.
+}
+
+impl BoundaryShape for Polygon {
+    fn height(&self, x: f64) -> f64 {
+        self.top_edge(x).map_or(f64::NAN, |(height, _)| height)
+    } // Returns NaN outside of the polygon, which is treated as there being no boundary at x.
+
+    fn slope(&self, x: f64) -> Option<f64> {
+        self.top_edge(x).map(|(_, slope)| slope)
+    }
+
+    fn x_extent(&self) -> Option<[f64;2]> {
+        Some(self.vertices.iter().fold([f64::INFINITY, f64::NEG_INFINITY], |[x_min, x_max], [x, _]| [x_min.min(*x), x_max.max(*x)]))
+    }
+}
+
+pub struct Analytic {
+    function: Box<dyn Fn(f64) -> f64 + Send + Sync>,
+    derivative: Box<dyn Fn(f64) -> f64 + Send + Sync>,
+}
+
+impl Analytic {
+    pub fn new<F, D>(function: F, derivative: D) -> Self
+    where
+        F: Fn(f64) -> f64 + Send + Sync + 'static,
+        D: Fn(f64) -> f64 + Send + Sync + 'static,
+    {
+        Self {
+            function: Box::new(function),
+            derivative: Box::new(derivative),
+        }
+    } // A function of x paired with its exact derivative, avoiding numerical differentiation at reflections.
+}
+
+impl BoundaryShape for Analytic {
+    fn height(&self, x: f64) -> f64 {
+        (self.function)(x)
+    }
+
+    fn slope(&self, x: f64) -> Option<f64> {
+        Some((self.derivative)(x))
+    }
+}
+
+#[derive(Clone)]
+pub struct Boundary {
+    shape_function : Arc<dyn BoundaryShape>,
     x_limits : [Option<f64>;2],
     y_maximum : Option<f64>,
     current_y : Option<f64>,
     material : Material,
+    roughness : Option<Roughness>,
 }
 
-impl<F: SingleInputFunction> Boundary<F> {
-    pub fn initialise(shape_function: Box<F>, material: MaterialType) -> Result<Self, &'static str>
-    where
-        F: SingleInputFunction + 'static,
-    {
+impl Boundary {
+    pub fn initialise(shape_function: Arc<dyn BoundaryShape>, material: MaterialType) -> Result<Self, &'static str> {
         let material_properites = Material::define(material);
 
         Ok(Boundary{
@@ -728,6 +1938,7 @@ impl<F: SingleInputFunction> Boundary<F> {
             y_maximum: Some(0.0),
             current_y: None,
             material: material_properites,
+            roughness: None,
         })
     }
 
@@ -739,6 +1950,10 @@ impl<F: SingleInputFunction> Boundary<F> {
         self.y_maximum = Some(limit);
     }
 
+    pub fn set_roughness(&mut self, roughness: Roughness) {
+        self.roughness = Some(roughness);
+    }
+
     fn boundary_height(&self, x:f64) -> Option<f64> {
         if let [Some(x_min), Some(x_max)] = self.x_limits {
             if x < x_min || x > x_max {
@@ -746,7 +1961,7 @@ impl<F: SingleInputFunction> Boundary<F> {
             }
         }
 
-        let mut y_boundary = self.shape_function.evaluate(x);
+        let mut y_boundary = self.shape_function.height(x);
 
         if let Some(y_max) = self.y_maximum {
             if y_boundary > y_max {
@@ -757,7 +1972,12 @@ impl<F: SingleInputFunction> Boundary<F> {
         Some(y_boundary)
     }
 
-    pub fn differentiate(&mut self, x_pos: f64) -> f64 {
+    pub fn differentiate(&self, x_pos: f64) -> f64 {
+        if let (Some(slope), Some(_)) = (self.shape_function.slope(x_pos), self.boundary_height(x_pos)) {
+            let capped = self.y_maximum.map_or(false, |y_max| self.shape_function.height(x_pos) > y_max);
+            return if capped { 0.0 } else { slope };
+        } // Uses the exact derivative when the shape provides one. The boundary is flat wherever it is capped by its maximum height.
+
         let h = 0.0000001;
         let mut result: f64 = f64::NAN;
         if let Some(next_height) = self.boundary_height( x_pos + h / 2.0 ) {
@@ -774,26 +1994,86 @@ impl<F: SingleInputFunction> Boundary<F> {
  
 }
 
-impl<F: SingleInputFunction + Clone> Clone for Boundary<F> {
-    fn clone(&self) -> Self {
-        Boundary {
-            shape_function: self.shape_function.clone(),
-            x_limits: self.x_limits,
-            y_maximum: self.y_maximum,
-            current_y: self.current_y,
-            material: self.material,
+//                                                  MARK: Boundary Index
+#[derive(Default)]
+pub struct BoundaryIndex {
+    x_range: [f64;2],
+    column_width: f64,
+    columns: Vec<Option<Vec<usize>>>,
+}
+
+impl BoundaryIndex {
+    pub fn build(boundaries: &[Boundary], x_range: [f64;2], column_width: f64) -> Self {
+        let number_of_columns = ((x_range[1] - x_range[0]) / column_width).ceil().max(0.0) as usize;
+        let mut columns = Vec::with_capacity(number_of_columns);
+
+        for column in 0..number_of_columns {
+            let edges = [x_range[0] + column as f64 * column_width, x_range[0] + (column + 1) as f64 * column_width];
+            let samples = [edges[0], (edges[0] + edges[1]) / 2.0, edges[1]];
+            let mut order: Vec<usize> = Vec::new();
+            let mut ordered = true;
+
+            for (i, boundary) in boundaries.iter().enumerate() {
+                let valid = samples.map(|x| boundary.boundary_height(x).is_some_and(|height| height.is_finite()));
+                let limit_inside = boundary.x_limits.iter().flatten().chain(boundary.shape_function.x_extent().iter().flatten())
+                    .any(|limit| *limit > edges[0] && *limit < edges[1]);
+                if valid.iter().all(|valid| *valid) && !limit_inside { order.push(i) }
+                else if valid.iter().any(|valid| *valid) || limit_inside { ordered = false }
+            } // Boundaries which start or stop part way across the column cannot be ordered.
+
+            order.sort_by(|a, b| {
+                let a_height = boundaries[*a].boundary_height(samples[1]).unwrap();
+                let b_height = boundaries[*b].boundary_height(samples[1]).unwrap();
+                a_height.partial_cmp(&b_height).unwrap()
+            }); // Sorts the interfaces from the bottom up at the centre of the column.
+
+            ordered &= samples.iter().all(|x| order.windows(2).all(|pair| {
+                boundaries[pair[0]].boundary_height(*x).unwrap() <= boundaries[pair[1]].boundary_height(*x).unwrap()
+            })); // Interfaces which cross inside the column cannot be ordered either.
+
+            columns.push(if ordered { Some(order) } else { None });
         }
-    }
+
+        BoundaryIndex {
+            x_range,
+            column_width,
+            columns,
+        }
+    } // Splits the range into columns and orders the interfaces in each one, so they do not need sorting for every ray.
+
+    fn boundary_above(&self, boundaries: &[Boundary], x: f64, y: f64) -> Option<(usize, f64)> {
+        let column = ((x - self.x_range[0]) / self.column_width).floor();
+        let order = match column >= 0.0 {
+            true => self.columns.get(column as usize).and_then(|order| order.as_ref()),
+            false => None,
+        };
+
+        if let Some(order) = order {
+            let first = order.partition_point(|i| boundaries[*i].boundary_height(x).is_some_and(|height| height <= y));
+            return order.get(first).and_then(|i| boundaries[*i].boundary_height(x).map(|height| (*i, height)));
+        } // Binary search through the ordered interfaces for the first one above y.
+
+        boundaries.iter().enumerate()
+            .filter_map(|(i, boundary)| boundary.boundary_height(x).filter(|height| height.is_finite() && *height > y).map(|height| (i, height)))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        // Outside the range, or where the order is not known, every boundary is checked.
+    } // Returns the index and height of the lowest boundary whose interface is above (x, y), if there is one.
 }
 
 //                                                  MARK: Grid Struct
 
 pub struct Grid {
-    squares: HashMap< (usize, usize), Option< (Vec<f64>, Vec<f64>) > >,
+    pressure: Vec<[f64;2]>,
+    channels: usize,
+    slots: HashMap<(usize, usize), usize>,
+    squares: Vec<(usize, usize)>,
+    columns: usize,
+    rows: usize,
     x_range: [f64;2],
     y_range: [f64;2],
     square_size: f64,
-}
+} // Holds the complex pressure, sqrt(I) e^(i phi), summed over the rays in each grid square which rays have reached, at each of its channels (frequencies).
+// Only those squares are stored, in the order rays first reached them, so the memory used does not depend on the size of the grid.
 
 impl Grid {
 
@@ -810,63 +2090,294 @@ impl Grid {
             eprintln!("Error: simulation_y_range[0] must be less than simulation_y_range[1].");
             std::process::exit(1);
         }
+        let columns = ((simulation_x_range[1] - simulation_x_range[0]) / square_size).ceil() as usize;
+        let rows = ((simulation_y_range[1] - simulation_y_range[0]) / square_size).ceil() as usize;
         Self {
-            squares: HashMap::new(),
+            pressure: Vec::new(),
+            channels: 1,
+            slots: HashMap::new(),
+            squares: Vec::new(),
+            columns,
+            rows,
             x_range: simulation_x_range,
             y_range: simulation_y_range,
             square_size: square_size,
         }
     }
 
-    fn append(&mut self, location: [f64; 2], intensity: f64, phase_shift: f64) -> () {
-        let x_grid: usize = ( (location[0] - self.x_range[0] ) / self.square_size) as usize;
-        let y_grid: usize = ( (location[1] - self.y_range[0] ) / self.square_size) as usize;
-        // Converts the position of the ray into a grid coordinate
-
-        if let Some(existing_intensity) = self.squares.get_mut(&(x_grid, y_grid)) {
-            // Get a mutable reference to the intensity value in the grid square
-            match existing_intensity {
-                Some((intensity_ref, phase_ref)) => {
-                    intensity_ref.push(intensity);
-                    phase_ref.push(phase_shift);
-                } // If the intensity and phase value exists, append the intensity and phase difference
-                None => {
-                    *existing_intensity = Some( (vec![intensity], vec![phase_shift]) );
-                } // If the intensity value doesn't exist, insert the new intensity and phase difference
-            }
-        } else {
-            self.squares.insert((x_grid, y_grid), Some( (vec![intensity], vec![phase_shift]) ));
-        } // If the grid square doesn't exist, create it and insert the new intensity and phase shift
-    }
-
-    fn output_data(&self) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+    fn clear(&mut self) {
+        self.pressure.clear();
+        self.slots.clear();
+        self.squares.clear();
+    } // The storage is kept, as the next frame usually reaches as many squares.
+
+    fn set_channels(&mut self, channels: usize) {
+        self.clear();
+        self.channels = channels;
+    } // Sets the number of frequencies kept in each grid square. Narrowband runs only need one.
+
+    fn square(&self, location: [f64;2]) -> Option<(usize, usize)> {
+        let x_grid = ((location[0] - self.x_range[0]) / self.square_size).floor();
+        let y_grid = ((location[1] - self.y_range[0]) / self.square_size).floor();
+        if x_grid < 0.0 || y_grid < 0.0 || x_grid >= self.columns as f64 || y_grid >= self.rows as f64 { return None }
+        Some((x_grid as usize, y_grid as usize))
+    } // Converts a position into a grid coordinate, if it is inside the simulation.
+
+    fn pressure(intensity: f64, phase_shift: f64) -> [f64;2] {
+        let amplitude = intensity.sqrt();
+        [amplitude * phase_shift.cos(), amplitude * phase_shift.sin()]
+    } // The complex pressure of a ray, sqrt(I) e^(i phi).
+
+    fn append(&mut self, location: [f64; 2], channel: usize, pressure: [f64;2]) {
+        let Some(square) = self.square(location) else { return };
+        let slot = *self.slots.entry(square).or_insert_with(|| {
+            self.squares.push(square);
+            self.pressure.resize(self.pressure.len() + self.channels, [0.0, 0.0]);
+            self.squares.len() - 1
+        });
+        let index = slot * self.channels + channel;
+        self.pressure[index][0] += pressure[0];
+        self.pressure[index][1] += pressure[1];
+    } // Adds the pressure of a ray, at one of its frequencies, to its grid square. Rays which have left the simulation, but not yet been removed, are left out.
+
+    fn square_centre(&self, square: (usize, usize)) -> [f64;2] {
+        [(square.0 as f64 + 0.5) * self.square_size + self.x_range[0], (square.1 as f64 + 0.5) * self.square_size + self.y_range[0]]
+    } // Converts a grid coordinate into the position of the centre of its grid square.
+
+    fn superimposed_intensities(&self, channel: usize) -> Vec<((usize, usize), f64)> {
+        self.squares.iter().zip(self.pressure.chunks(self.channels)).map(|(square, pressure)| {
+            let [real, imaginary] = pressure[channel];
+            (*square, real.powi(2) + imaginary.powi(2))
+        }).collect()
+        // |Sum over all i (sqrt(I_i) e^(i phi_i))|^2 = Sum over all i (I_i) + 2 * Sum over all i,j>i (sqrt(I_i * I_j) cos(phi_i - phi_j))
+    } // Returns the superimposed intensity at a channel in each grid square containing rays, keyed by its grid coordinate, in the order rays first reached them.
+
+    fn output_data(&self, values: Vec<((usize, usize), f64)>) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
         let mut x_positions = Vec::new();
         let mut y_positions = Vec::new();
         let mut intensities = Vec::new();
     
-        for ((x, y), ray_data) in &self.squares {
-            let mut superimposed_intensity: f64 = 0.0;
+        for (square, superimposed_intensity) in values {
+            let [x_position, y_position] = self.square_centre(square);
+            x_positions.push(x_position);
+            y_positions.push(y_position);
+            intensities.push(superimposed_intensity);
+            // Appends data to output. Position data is converted to output the centre of its grid square.
+        }
+        (x_positions, y_positions, intensities)
+        // Return a tuple containing the vectors of x positions, y positions, intensities
+    }
+}
 
-            if let Some((grid_intensities, grid_phases)) = ray_data {
-                // Check if the grid square contains intensity values
 
-                for i in 0..grid_intensities.len() {
-                    if grid_intensities.len() != 1 {
-                        for j in (i+1)..grid_intensities.len() {
-                                superimposed_intensity += 2.0 * f64::sqrt(grid_intensities[i] * grid_intensities[j]) * (grid_phases[i] - grid_phases[j]).cos()
-                        }
-                    }
-                    superimposed_intensity += grid_intensities[i];
-                } // I_eff = Sum over all i=1...N (I_i) + 2 * Sum over all i,j>i ( sqrt(I_i * I_j) cos(phi_i - phi_j))
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                x_positions.push( (*x as f64 + 0.5) * self.square_size + self.x_range[0]);
-                y_positions.push( -1.0 * ((*y as f64 + 0.5) * self.square_size + self.y_range[0]) );
-                intensities.push(superimposed_intensity);
-                // Appends data to output. Position data is converted to output the centre of its grid square.
+    #[test]
+    fn refraction_without_a_current_follows_snells_law() {
+        let (angle, step_vector) = moving_medium_refraction(0.5, 1.0, [1500.0, 1520.0], [0.0, 0.0]);
+        assert_eq!(step_vector, 1.0);
+        assert!((angle.sin() / 1520.0 - 0.5_f64.sin() / 1500.0).abs() < 1e-15);
+    }
+
+    #[test]
+    fn refraction_past_the_turning_point_reflects_the_ray() {
+        let (angle, step_vector) = moving_medium_refraction(1.55, 1.0, [1500.0, 1500.0], [0.0, 2.0]);
+        assert_eq!((angle, step_vector), (-1.55, -1.0));
+        // The horizontal direction of travel is kept and the vertical direction is reversed.
+
+        let (angle, _) = moving_medium_refraction(1.55, 1.0, [1500.0, 1500.0], [0.0, -2.0]);
+        assert!(angle < 1.55);
+        // A current against the ray bends it back towards the vertical.
+    }
+
+    #[test]
+    fn grids_only_store_the_squares_rays_reach() {
+        let mut grid = Grid::initialise(0.01, [-5000.0, 5000.0], [-5000.0, 5000.0]);
+        grid.append([4999.999, 4999.999], 0, [1.0, 0.0]);
+        grid.append([-5000.0, -5000.0], 0, [0.0, 2.0]);
+        grid.append([4999.995, 4999.995], 0, [1.0, 0.0]);
+        grid.append([6000.0, 0.0], 0, [1.0, 0.0]);
+        assert_eq!(grid.superimposed_intensities(0), vec![((999999, 999999), 4.0), ((0, 0), 4.0)]);
+        assert_eq!(grid.pressure.len(), 2);
+
+        grid.clear();
+        assert!(grid.superimposed_intensities(0).is_empty());
+    }
+
+    #[test]
+    fn grids_keep_every_channel_for_the_squares_rays_reach() {
+        let mut grid = Grid::initialise(1.0, [0.0, 10.0], [0.0, 10.0]);
+        grid.set_channels(3);
+        grid.append([5.5, 5.5], 2, [0.0, 3.0]);
+        grid.append([1.5, 1.5], 0, [1.0, 0.0]);
+        grid.append([5.5, 5.5], 2, [0.0, -1.0]);
+        assert_eq!(grid.superimposed_intensities(0), vec![((5, 5), 0.0), ((1, 1), 1.0)]);
+        assert_eq!(grid.superimposed_intensities(1), vec![((5, 5), 0.0), ((1, 1), 0.0)]);
+        assert_eq!(grid.superimposed_intensities(2), vec![((5, 5), 4.0), ((1, 1), 0.0)]);
+    }
+
+    fn linear_scan(boundaries: &[Boundary], x: f64, y: f64) -> Option<(usize, f64)> {
+        let mut valid: Vec<(usize, f64)> = boundaries.iter().enumerate()
+            .filter_map(|(i, boundary)| boundary.boundary_height(x).filter(|height| height.is_finite()).map(|height| (i, height)))
+            .collect();
+        valid.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        valid.into_iter().find(|(_, height)| *height > y)
+    } // Sorts every boundary at x, as ray_speed did before the index, and takes the lowest one above y.
+
+    #[test]
+    fn indexed_boundaries_match_a_linear_scan() {
+        let shapes: Vec<(Arc<dyn BoundaryShape>, Option<[f64;2]>)> = vec![
+            (Arc::new(|_x: f64| -500.0), None),
+            (Arc::new(|x: f64| -800.0 + 0.4 * x), None),
+            (Arc::new(|x: f64| -300.0 + 0.1 * x), Some([-333.3, 421.7])),
+            (Arc::new(Polygon::new(vec![[-200.0, -900.0], [0.0, -600.0], [150.0, -900.0]])), None),
+            (Arc::new(Polygon::new(vec![[600.0, -700.0], [800.0, -450.0], [950.0, -700.0]])), None),
+            (Arc::new(Polygon::new(vec![[-502.0, -950.0], [-501.0, -400.0], [-500.0, -950.0]])), None),
+        ]; // The slope crosses the flat interface at x = 750, inside the second polygon. The limited interface and the last polygon start and stop part way across columns, the polygon between the points each column is sampled at.
+        let boundaries: Vec<Boundary> = shapes.into_iter().map(|(shape, limits)| {
+            let mut boundary = Boundary::initialise(shape, MaterialType::Sand).unwrap();
+            if let Some(limits) = limits { boundary.set_x_limits(limits) }
+            boundary
+        }).collect();
+
+        let index = BoundaryIndex::build(&boundaries, [-1000.0, 1000.0], 7.0);
+        assert!(index.columns.iter().any(|order| order.is_none()));
+        assert!(index.columns.iter().any(|order| order.as_ref().is_some_and(|order| order.len() > 1)));
+        // Both the binary search and the fallback are used.
+
+        for i in 0..600 {
+            let x = -1100.0 + 3.7 * i as f64;
+            for j in 0..120 {
+                let y = -1000.0 + 9.1 * j as f64;
+                assert_eq!(index.boundary_above(&boundaries, x, y), linear_scan(&boundaries, x, y), "at ({}, {})", x, y);
             }
+        } // Includes points outside of the indexed range.
+    }
+
+    fn stepped(mut simulation: Simulation, threads: usize) -> Simulation {
+        simulation.set_threads(threads);
+        let max_init_intensity = simulation.launch_rays();
+        for i in 1..=100 {
+            simulation.step_rays(0.002, i as f64 * 0.002, max_init_intensity);
         }
-        (x_positions, y_positions, intensities)
-        // Return a tuple containing the vectors of x positions, y positions, intensities
+        simulation.superimpose_rays();
+        simulation
+    } // Steps the rays for 0.2 s, by which time they have travelled about 300 m, and superimposes them on the grid.
+
+    fn free_field(number_of_rays: i32) -> Simulation {
+        let mut simulation = Simulation::new(5.0, [-1000.0, 1000.0], [-2000.0, 0.0]);
+        simulation.add_source(-PI, PI, number_of_rays, 180.0, 50.0, [0.0, -1000.0], SourceType::Point);
+        stepped(simulation, 1)
+    } // A single source in open water.
+
+    fn shallow_water(threads: usize) -> Simulation {
+        let mut simulation = Simulation::new(5.0, [-1000.0, 1000.0], [-1000.0, 0.0]);
+        simulation.add_boundary(MaterialType::Sand, |x: f64| -200.0 - 0.05 * x);
+        simulation.add_source(-PI, PI, 3000, 180.0, 50.0, [0.0, -50.0], SourceType::Point);
+        simulation.log_interactions();
+        stepped(simulation, threads)
+    } // A source between the sea surface and a sloping sand bottom, so that the rays reflect and split during the steps.
+
+    #[test]
+    fn levels_do_not_depend_on_the_number_of_rays() {
+        let mean_level = |number_of_rays: i32| {
+            let (intensities, _) = free_field(number_of_rays).superimposed_intensities();
+            10.0 * (intensities.iter().map(|(_, intensity)| intensity).sum::<f64>() / intensities.len() as f64).log10()
+        };
+        let (level, doubled_level) = (mean_level(2000), mean_level(4000));
+        assert!((level - doubled_level).abs() < 0.5, "{} dB with 2000 rays, {} dB with 4000 rays", level, doubled_level);
     }
-}
 
+    #[test]
+    fn refinement_keeps_the_width_and_pressure_of_the_wavefront() {
+        let mut simulation = free_field(2000);
+        let total = |simulation: &Simulation| simulation.grid.pressure.iter().fold([0.0, 0.0], |total, pressure| [total[0] + pressure[0], total[1] + pressure[1]]);
+        let (spacing, pressure) = (simulation.rays.angular_spacing.iter().sum::<f64>(), total(&simulation));
+
+        for _ in 0..5 { simulation.rays.refine(0.1, 3) }
+        simulation.superimpose_rays();
+        // The rays are about 1 m apart, so every gap would be halved four times without the limit on the depth.
+
+        assert_eq!(simulation.rays.x_pos.len(), 2000 + 1999 * 7);
+        assert!(simulation.rays.refinement_depth.iter().all(|depth| *depth <= 3));
+        assert!((simulation.rays.angular_spacing.iter().sum::<f64>() - spacing).abs() < 1e-12 * spacing);
+        let refined = total(&simulation);
+        assert!((refined[0] - pressure[0]).hypot(refined[1] - pressure[1]) < 1e-3 * pressure[0].hypot(pressure[1]), "{:?} became {:?}", pressure, refined);
+    } // Far from the source, each ray adds pressure to the grid in proportion to the angle it stands for, so the coherent sum over the grid stays the same.
+
+    #[test]
+    fn only_the_seabed_counts_bottom_bounces() {
+        let mut simulation = Simulation::new(5.0, [-1000.0, 1000.0], [-1000.0, 0.0]);
+        simulation.add_boundary(MaterialType::Sand, |_x: f64| -200.0);
+        simulation.add_boundary(MaterialType::TurbiditeArea, |_x: f64| -300.0);
+        simulation.add_source(0.85, 0.95, 200, 180.0, 50.0, [-300.0, -150.0], SourceType::Point);
+        simulation.add_source(0.85, 0.95, 200, 180.0, 50.0, [300.0, -250.0], SourceType::Point);
+        simulation.log_interactions();
+        simulation.set_threads(1);
+        let max_init_intensity = simulation.launch_rays();
+        for i in 1..=60 {
+            simulation.step_rays(0.002, i as f64 * 0.002, max_init_intensity);
+        } // The rays from the water reflect off the seabed, and those from inside the sand reflect off the slower sediment below it,
+        // so none are totally reflected. Neither have reached another interface since.
+
+        let rays = &simulation.rays;
+        let events = rays.events.as_deref().unwrap();
+        let reflected_by = |interface: Interface| -> Vec<u32> {
+            events.iter()
+                .filter(|event| event.interaction == Interaction::Reflection && event.interface == Some(interface))
+                .filter_map(|event| rays.id.iter().position(|id| *id == event.ray))
+                .filter(|i| rays.parent[*i].is_some_and(|parent| parent < 400))
+                .map(|i| rays.bottom_bounces[i])
+                .collect()
+        }; // The bottom bounces of the rays still going which were reflected off the interface by a launched ray.
+
+        let (seabed, basalt) = (reflected_by(Interface::Boundary(0)), reflected_by(Interface::Boundary(1)));
+        assert!(!seabed.is_empty() && !basalt.is_empty());
+        assert!(seabed.iter().all(|bounces| *bounces == 1));
+        assert!(basalt.iter().all(|bounces| *bounces == 0));
+        assert!(!events.iter().any(|event| event.interaction == Interaction::TotalInternalReflection));
+    }
+
+    #[test]
+    fn total_internal_reflections_are_not_transmissions() {
+        let mut simulation = Simulation::new(5.0, [-1000.0, 1000.0], [-1000.0, 0.0]);
+        simulation.add_boundary(MaterialType::Sand, |_x: f64| -100.0);
+        simulation.add_source(-PI, PI, 1000, 180.0, 50.0, [0.0, -50.0], SourceType::Point);
+        simulation.log_interactions();
+        let simulation = stepped(simulation, 1);
+        // The seabed is shallow enough for rays to reach it beyond the critical angle.
+        let events = simulation.rays.events.as_deref().unwrap();
+        let reflected: Vec<(usize, u64)> = events.iter()
+            .filter(|event| event.interaction == Interaction::TotalInternalReflection)
+            .map(|event| (event.ray, event.time.to_bits()))
+            .collect();
+        assert!(!reflected.is_empty());
+        assert!(!events.iter().any(|event| event.interaction == Interaction::Transmission && reflected.contains(&(event.ray, event.time.to_bits()))));
+    } // A ray turned back at an interface does not cross it.
+
+    #[test]
+    fn stepping_does_not_depend_on_the_number_of_threads() {
+        let (single, multiple) = (shallow_water(1), shallow_water(4));
+        let (rays, other) = (&single.rays, &multiple.rays);
+        assert!(rays.next_id > 3000);
+        // Rays have reflected and split, so they were created on several threads.
+        assert_eq!(rays.x_pos, other.x_pos);
+        assert_eq!(rays.y_pos, other.y_pos);
+        assert_eq!(rays.angle, other.angle);
+        assert_eq!(rays.step_vector, other.step_vector);
+        assert_eq!(rays.intensity, other.intensity);
+        assert_eq!(rays.phase_shift, other.phase_shift);
+        assert_eq!(rays.id, other.id);
+        assert_eq!(rays.parent, other.parent);
+        assert_eq!(rays.surface_bounces, other.surface_bounces);
+        assert_eq!(rays.bottom_bounces, other.bottom_bounces);
+        assert_eq!(rays.next_id, other.next_id);
+        assert_eq!(events::encode(rays.events.as_deref().unwrap()), events::encode(other.events.as_deref().unwrap()));
+        let grid = |simulation: &Simulation| -> Vec<((usize, usize), u64)> {
+            simulation.superimposed_intensities().0.into_iter().map(|(square, intensity)| (square, intensity.to_bits())).collect()
+        }; // Compared bit for bit, so squares which are not a number must match too.
+        assert_eq!(grid(&single), grid(&multiple));
+    }
+}
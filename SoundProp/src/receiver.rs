@@ -0,0 +1,186 @@
+use crate::{trajectory::Trajectory, waveform::{hilbert, Waveform}};
+
+//                                                  MARK: Receiver Struct
+#[derive(Clone, Debug)]
+pub struct Receiver {
+    location: [f64;2],
+    trajectory: Option<Trajectory>,
+    radius: f64,
+    sample_rate: f64,
+    arrivals: Vec<Arrival>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arrival {
+    pub source: usize,
+    pub emission_time: f64,
+    pub delay: f64,
+    pub amplitude: f64,
+    pub phase: f64,
+    pub doppler: f64,
+    pub surface_bounces: u32,
+    pub bottom_bounces: u32,
+} // A single ray passing the receiver, with its pressure amplitude (µPa), phase shift from reflections,
+// the ratio of the received to the emitted frequency and the number of times it has bounced off the surface and the seabed.
+
+impl Receiver {
+    pub fn new(location: [f64;2], radius: f64, sample_rate: f64) -> Self {
+        if radius <= 0.0 {
+            eprintln!("Error: radius must be a positive, non-zero, float value.");
+            std::process::exit(1);
+        }
+        if sample_rate <= 0.0 {
+            eprintln!("Error: sample_rate must be a positive, non-zero, float value.");
+            std::process::exit(1);
+        }
+
+        Self {
+            location,
+            trajectory: None,
+            radius,
+            sample_rate,
+            arrivals: Vec::new(),
+        }
+    }
+
+    pub fn set_trajectory(&mut self, trajectory: Trajectory) {
+        self.location = trajectory.start();
+        self.trajectory = Some(trajectory);
+    } // Moves the receiver along the trajectory from the start of the run.
+
+    pub fn position(&self, time: f64) -> [f64;2] {
+        match &self.trajectory {
+            Some(trajectory) => trajectory.position(time),
+            None => self.location,
+        }
+    }
+
+    pub fn velocity(&self, time: f64) -> [f64;2] {
+        match &self.trajectory {
+            Some(trajectory) => trajectory.velocity(time),
+            None => [0.0, 0.0],
+        }
+    }
+
+    pub fn arrivals(&self) -> &[Arrival] {
+        &self.arrivals
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn clear(&mut self) {
+        self.arrivals.clear();
+    }
+
+    pub fn add_arrival(&mut self, arrival: Arrival) {
+        self.arrivals.push(arrival);
+    }
+
+    pub fn detect(&self, segment: [[f64;2];2], times: [f64;2], speed: f64, tube_width: f64, mut arrival: Arrival) -> Option<Arrival> {
+        let [old_position, new_position] = segment;
+        let delta = [new_position[0] - old_position[0], new_position[1] - old_position[1]];
+        let length_squared = delta[0].powi(2) + delta[1].powi(2);
+        if length_squared == 0.0 { return None }
+
+        let location = self.position(times[0]);
+        let fraction = ((location[0] - old_position[0]) * delta[0] + (location[1] - old_position[1]) * delta[1]) / length_squared;
+        if !(0.0..1.0).contains(&fraction) { return None }
+        // Only rays which pass their closest point to the receiver during this step arrive, so each ray is counted once whichever way it travels.
+
+        let closest = [old_position[0] + fraction * delta[0], old_position[1] + fraction * delta[1]];
+        if (closest[0] - location[0]).hypot(closest[1] - location[1]) > self.radius { return None }
+
+        let velocity = self.velocity(times[0]);
+        let length = length_squared.sqrt();
+        arrival.doppler *= 1.0 - (velocity[0] * delta[0] + velocity[1] * delta[1]) / (length * speed);
+        // A receiver moving along with the ray hears a lower frequency.
+
+        arrival.delay += fraction * (times[1] - times[0]);
+        arrival.amplitude *= (tube_width / (2.0 * self.radius)).min(1.0);
+        // Every ray of a wavefront within the receiver is picked up, so each only adds its share of the wavefront's pressure.
+        Some(arrival)
+    } // Takes a ray's positions and the simulation times at the start and end of a step, the width of wavefront it stands for, and the ray's arrival at the start of the step.
+    // Returns the arrival, at the point closest to the receiver, if the ray passes within the radius of the receiver.
+
+    pub fn synthesise(&self, waveforms: &[Waveform]) -> Vec<f64> {
+        let sampled: Vec<(Vec<f64>, Vec<f64>)> = waveforms.iter().map(|waveform| {
+            let signal = waveform.samples(self.sample_rate);
+            let quadrature = hilbert(&signal);
+            (signal, quadrature)
+        }).collect();
+
+        let length = self.arrivals.iter()
+            .map(|arrival| ((arrival.emission_time + arrival.delay) * self.sample_rate).ceil() as usize + (sampled[arrival.source].0.len() as f64 / arrival.doppler).ceil() as usize)
+            .max().unwrap_or(0);
+        let mut pressure = vec![0.0; length];
+
+        for arrival in &self.arrivals {
+            let (signal, quadrature) = &sampled[arrival.source];
+            let offset = (arrival.emission_time + arrival.delay) * self.sample_rate;
+            let start = offset.ceil() as usize;
+            let (in_phase, out_of_phase) = (arrival.amplitude * arrival.phase.cos(), arrival.amplitude * arrival.phase.sin());
+
+            for (m, value) in pressure.iter_mut().enumerate().skip(start) {
+                let position = (m as f64 - offset) * arrival.doppler;
+                let n = position.floor() as usize;
+                if n >= signal.len() { break }
+                // The Doppler shift compresses (or stretches) the waveform in time.
+                let fraction = position - n as f64;
+                let interpolate = |samples: &Vec<f64>| {
+                    let next = if n + 1 < samples.len() { samples[n + 1] } else { 0.0 };
+                    samples[n] + fraction * (next - samples[n])
+                }; // The arrival rarely lands on a sample, so the waveform is shifted by a fraction of a sample.
+                *value += in_phase * interpolate(signal) - out_of_phase * interpolate(quadrature);
+            }
+        } // Convolves the arrivals with each source's waveform, starting at the time each ray arrives.
+
+        pressure
+    } // Returns the received pressure time series, sampled from t = 0.0, given the waveform of each source.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arrival(delay: f64) -> Arrival {
+        Arrival { source: 0, emission_time: 0.0, delay: delay, amplitude: 1.0, phase: 0.0, doppler: 1.0, surface_bounces: 0, bottom_bounces: 0 }
+    }
+
+    fn arrivals(receiver: &Receiver, path: &[[f64;2]]) -> Vec<Arrival> {
+        path.windows(2).enumerate()
+            .filter_map(|(i, segment)| receiver.detect([segment[0], segment[1]], [i as f64, i as f64 + 1.0], 1500.0, 1.0, arrival(i as f64)))
+            .collect()
+    } // Steps a ray along the path, taking one second for each segment.
+
+    #[test]
+    fn detects_vertical_rays() {
+        let receiver = Receiver::new([0.0, -150.0], 5.0, 8000.0);
+        let path: Vec<[f64;2]> = (0..300).map(|i| [0.0, -(i as f64)]).collect();
+        let detected = arrivals(&receiver, &path);
+        assert_eq!(detected.len(), 1);
+        assert!((detected[0].delay - 150.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn detects_near_vertical_rays() {
+        let receiver = Receiver::new([0.0, -150.0], 5.0, 8000.0);
+        let path: Vec<[f64;2]> = (0..300).map(|i| [-3.0 + 0.02 * i as f64, -(i as f64)]).collect();
+        assert_eq!(arrivals(&receiver, &path).len(), 1);
+    }
+
+    #[test]
+    fn ignores_rays_outside_the_radius() {
+        let receiver = Receiver::new([0.0, -150.0], 5.0, 8000.0);
+        let path: Vec<[f64;2]> = (0..300).map(|i| [10.0, -(i as f64)]).collect();
+        assert!(arrivals(&receiver, &path).is_empty());
+    }
+
+    #[test]
+    fn detects_horizontal_rays_once() {
+        let receiver = Receiver::new([150.0, -50.0], 5.0, 8000.0);
+        let path: Vec<[f64;2]> = (0..300).map(|i| [i as f64, -48.0]).collect();
+        assert_eq!(arrivals(&receiver, &path).len(), 1);
+    }
+}
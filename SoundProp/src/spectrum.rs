@@ -0,0 +1,73 @@
+//                                                  MARK: Spectrum Struct
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spectrum {
+    frequencies: Vec<f64>,
+    levels: Vec<f64>,
+} // The frequencies emitted by a source, in ascending order, with the fraction of the source intensity at each.
+
+impl Spectrum {
+    pub fn tone(frequency: f64) -> Self {
+        Self::list(vec![frequency], vec![0.0])
+    } // A single frequency, which is what every source emits by default.
+
+    pub fn band(lower_frequency: f64, upper_frequency: f64, number_of_frequencies: usize) -> Self {
+        if number_of_frequencies == 0 {
+            eprintln!("Error: number_of_frequencies must be a positive, non-zero, integer value.");
+            std::process::exit(1);
+        }
+        if lower_frequency <= 0.0 || upper_frequency < lower_frequency {
+            eprintln!("Error: The band must have a positive lower frequency which is not greater than its upper frequency.");
+            std::process::exit(1);
+        }
+
+        let frequencies: Vec<f64> = match number_of_frequencies {
+            1 => vec![(lower_frequency * upper_frequency).sqrt()],
+            _ => (0..number_of_frequencies).map(|k| {
+                lower_frequency * (upper_frequency / lower_frequency).powf(k as f64 / (number_of_frequencies - 1) as f64)
+            }).collect(),
+        }; // Logarithmically spaced, so a single frequency sits at the geometric centre of the band.
+
+        Self::list(frequencies, vec![0.0; number_of_frequencies])
+    } // Spreads the source intensity evenly over N frequencies between the edges of the band.
+
+    pub fn list(frequencies: Vec<f64>, levels: Vec<f64>) -> Self {
+        if frequencies.is_empty() || frequencies.len() != levels.len() {
+            eprintln!("Error: A spectrum must have one level for each of its frequencies.");
+            std::process::exit(1);
+        }
+        if frequencies.iter().any(|frequency| frequency.is_nan() || *frequency <= 0.0) {
+            eprintln!("Error: frequency must be a positive, non-zero, float value.");
+            std::process::exit(1);
+        }
+
+        let mut components: Vec<(f64, f64)> = frequencies.into_iter()
+            .zip(levels.into_iter().map(|level| 10.0_f64.powf(level / 10.0)))
+            .collect();
+        components.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        if components.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            eprintln!("Error: Each frequency in a spectrum must be unique.");
+            std::process::exit(1);
+        }
+
+        let total: f64 = components.iter().map(|(_, level)| level).sum();
+        Self {
+            frequencies: components.iter().map(|(frequency, _)| *frequency).collect(),
+            levels: components.iter().map(|(_, level)| level / total).collect(),
+        }
+    } // Takes the level of each frequency in dB, relative to one another. The levels are normalised so the source intensity is unchanged.
+
+    pub fn frequencies(&self) -> &[f64] {
+        &self.frequencies
+    }
+
+    pub fn levels(&self) -> &[f64] {
+        &self.levels
+    } // The fraction of the source intensity at each frequency, which sum to 1.0.
+
+    pub fn level_at(&self, frequency: f64) -> f64 {
+        match self.frequencies.iter().position(|f| *f == frequency) {
+            Some(k) => self.levels[k],
+            None => 0.0,
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::f64::consts::PI;
+
+use crate::{material::Material, random::Random};
+
+const GRAVITY: f64 = 9.81; // m s^-2
+
+//                                                  MARK: Sea Surface Struct
+#[derive(Clone, Debug)]
+pub struct SeaSurface {
+    components: Vec<WaveComponent>,
+    spectrum: Option<WaveSpectrum>,
+    roughness: Option<Roughness>,
+    ice: Option<IceCover>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum WaveSpectrum {
+    PiersonMoskowitz { wind_speed: f64, number_of_components: usize },
+    Jonswap { wind_speed: f64, fetch: f64, gamma: f64, number_of_components: usize },
+} // Kept by random surfaces so that new realisations can be drawn from the same sea state.
+
+#[derive(Clone, Copy, Debug)]
+struct WaveComponent {
+    amplitude: f64,
+    wavenumber: f64,
+    angular_frequency: f64,
+    phase: f64,
+} // A single sinusoidal wave travelling in the +x direction (or -x for a negative wavenumber).
+
+impl SeaSurface {
+    pub fn flat() -> Self {
+        Self {
+            components: Vec::new(),
+            spectrum: None,
+            roughness: None,
+            ice: None,
+        }
+    } // A flat, fixed surface at y = 0.0, which is the default for every simulation.
+
+    pub fn sinusoids(waves: Vec<[f64;3]>) -> Self {
+        let mut components = Vec::with_capacity(waves.len());
+        for [amplitude, wavelength, phase] in waves {
+            if wavelength == 0.0 {
+                eprintln!("Error: The wavelength of a surface wave must be non-zero.");
+                std::process::exit(1);
+            }
+            let wavenumber = 2.0 * PI / wavelength;
+            components.push(WaveComponent {
+                amplitude,
+                wavenumber,
+                angular_frequency: (GRAVITY * wavenumber.abs()).sqrt(),
+                phase,
+            });
+        } // Each wave travels with the deep water dispersion relation, w^2 = g k.
+
+        Self {
+            components,
+            spectrum: None,
+            roughness: None,
+            ice: None,
+        }
+    } // Defines the surface as a sum of [amplitude, wavelength, phase] sinusoids. Negative wavelengths travel in the -x direction.
+
+    pub fn pierson_moskowitz(wind_speed: f64, number_of_components: usize, seed: u64) -> Self {
+        check_sea_state(wind_speed, number_of_components);
+        Self::from_spectrum(WaveSpectrum::PiersonMoskowitz { wind_speed, number_of_components }, seed)
+    } // A fully developed sea for a wind speed (m/s, at 19.5 m), realised from the given seed.
+
+    pub fn jonswap(wind_speed: f64, fetch: f64, gamma: f64, number_of_components: usize, seed: u64) -> Self {
+        check_sea_state(wind_speed, number_of_components);
+        if fetch <= 0.0 || gamma < 1.0 {
+            eprintln!("Error: fetch must be positive and non-zero, and gamma must be at least 1.0.");
+            std::process::exit(1);
+        }
+        Self::from_spectrum(WaveSpectrum::Jonswap { wind_speed, fetch, gamma, number_of_components }, seed)
+    } // A fetch limited sea for a wind speed (m/s, at 10 m) and fetch (m), realised from the given seed.
+
+    fn from_spectrum(spectrum: WaveSpectrum, seed: u64) -> Self {
+        let mut surface = Self::flat();
+        surface.spectrum = Some(spectrum);
+        surface.reseed(seed);
+        surface
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        match self.spectrum {
+            Some(WaveSpectrum::PiersonMoskowitz { wind_speed, number_of_components }) => {
+                let peak_frequency = 0.877 * GRAVITY / wind_speed;
+                self.components = Self::realise(peak_frequency, number_of_components, seed, |w| {
+                    8.1e-3 * GRAVITY.powi(2) * w.powi(-5) * (-0.74 * (GRAVITY / (wind_speed * w)).powi(4)).exp()
+                });
+            }
+            Some(WaveSpectrum::Jonswap { wind_speed, fetch, gamma, number_of_components }) => {
+                let peak_frequency = 22.0 * (GRAVITY.powi(2) / (wind_speed * fetch)).powf(1.0 / 3.0);
+                let alpha = 0.076 * (wind_speed.powi(2) / (fetch * GRAVITY)).powf(0.22);
+                self.components = Self::realise(peak_frequency, number_of_components, seed, |w| {
+                    let sigma: f64 = if w <= peak_frequency { 0.07 } else { 0.09 };
+                    let peak_enhancement = gamma.powf((-(w - peak_frequency).powi(2) / (2.0 * sigma.powi(2) * peak_frequency.powi(2))).exp());
+                    alpha * GRAVITY.powi(2) * w.powi(-5) * (-1.25 * (peak_frequency / w).powi(4)).exp() * peak_enhancement
+                });
+            }
+            None => (),
+        }
+    } // Draws a new random realisation of the same sea state. Flat and sinusoidal surfaces are left unchanged.
+
+    fn realise<S: Fn(f64) -> f64>(peak_frequency: f64, number_of_components: usize, seed: u64, spectrum: S) -> Vec<WaveComponent> {
+        let mut random = Random::seed(seed);
+        let lowest_frequency = 0.5 * peak_frequency;
+        let highest_frequency = 3.0 * peak_frequency;
+        let bandwidth = (highest_frequency - lowest_frequency) / number_of_components as f64;
+        let mut components = Vec::with_capacity(number_of_components);
+
+        for i in 0..number_of_components {
+            let angular_frequency = lowest_frequency + (i as f64 + random.uniform()) * bandwidth;
+            // Randomly places each component inside of its band, so that the surface does not repeat itself.
+            components.push(WaveComponent {
+                amplitude: (2.0 * spectrum(angular_frequency) * bandwidth).sqrt(),
+                wavenumber: angular_frequency.powi(2) / GRAVITY,
+                angular_frequency,
+                phase: 2.0 * PI * random.uniform(),
+            });
+        }
+        components
+    } // Discretises a wave spectrum S(w) between 0.5 and 3 times its peak frequency, with random phases.
+
+    pub fn set_roughness(&mut self, roughness: Roughness) {
+        self.roughness = Some(roughness);
+    } // Adds small scale roughness on top of the waves, which are too small to be resolved by the surface components.
+
+    pub fn roughness(&self) -> Option<Roughness> {
+        self.roughness
+    }
+
+    pub fn set_ice_cover(&mut self, ice: IceCover) {
+        self.ice = Some(ice);
+    } // Covers the surface with a layer of sea ice, whose underside follows the surface height.
+
+    pub fn ice_cover(&self) -> Option<&IceCover> {
+        self.ice.as_ref()
+    }
+
+    pub fn ice_cover_mut(&mut self) -> Option<&mut IceCover> {
+        self.ice.as_mut()
+    }
+
+    pub fn height(&self, x: f64, time: f64) -> f64 {
+        self.components.iter()
+            .map(|wave| wave.amplitude * (wave.wavenumber * x - wave.angular_frequency * time + wave.phase).cos())
+            .sum()
+    } // Returns the height of the surface, eta(x, t), in metres above y = 0.0.
+
+    pub fn slope(&self, x: f64, time: f64) -> f64 {
+        self.components.iter()
+            .map(|wave| -wave.amplitude * wave.wavenumber * (wave.wavenumber * x - wave.angular_frequency * time + wave.phase).sin())
+            .sum()
+    } // Returns the instantaneous slope of the surface, d(eta)/dx, used to find the normal at surface reflections.
+}
+
+impl Default for SeaSurface {
+    fn default() -> Self {
+        Self::flat()
+    }
+}
+
+fn check_sea_state(wind_speed: f64, number_of_components: usize) {
+    if wind_speed <= 0.0 {
+        eprintln!("Error: wind_speed must be a positive, non-zero, float value.");
+        std::process::exit(1);
+    }
+    if number_of_components == 0 {
+        eprintln!("Error: number_of_components must be a positive, non-zero, integer value.");
+        std::process::exit(1);
+    }
+}
+
+//                                                  MARK: Ice Cover Struct
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IceCover {
+    thickness: f64,
+    material: Material,
+    attenuation: f64,
+    roughness: Option<Roughness>,
+}
+
+impl IceCover {
+    pub fn new(thickness: f64, material: Material, attenuation: f64) -> Self {
+        if thickness <= 0.0 {
+            eprintln!("Error: The thickness of the ice must be a positive, non-zero, float value.");
+            std::process::exit(1);
+        }
+        if attenuation < 0.0 {
+            eprintln!("Error: The attenuation of the ice must be a positive float value.");
+            std::process::exit(1);
+        }
+
+        Self {
+            thickness,
+            material,
+            attenuation,
+            roughness: None,
+        }
+    } // Initialisation function for an ice layer of a given thickness (m), elastic material and attenuation (dB per wavelength).
+
+    pub fn set_roughness(&mut self, roughness: Roughness) {
+        self.roughness = Some(roughness);
+    } // Roughness of the underside of the ice, which is seen by rays reflecting from below.
+
+    pub fn roughness(&self) -> Option<Roughness> {
+        self.roughness
+    }
+
+    pub fn thickness(&self) -> f64 {
+        self.thickness
+    }
+
+    pub fn material(&self) -> Material {
+        self.material
+    }
+
+    pub fn attenuation(&self, frequency: f64, speed: f64, distance: f64) -> f64 {
+        10.0_f64.powf(-self.attenuation * distance * frequency / speed / 10.0)
+    } // Returns the fraction of energy left after travelling a distance through the ice.
+}
+
+//                                                  MARK: Roughness Struct
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scattering {
+    Specular,
+    Lambert(usize),
+    Kirchhoff(usize),
+} // How the energy lost from the specular reflection is treated: discarded, or scattered into the given number of diffuse rays.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Roughness {
+    rms_height: f64,
+    correlation_length: f64,
+    scattering: Scattering,
+}
+
+impl Roughness {
+    pub fn new(rms_height: f64, correlation_length: f64, scattering: Scattering) -> Self {
+        if rms_height < 0.0 {
+            eprintln!("Error: rms_height must be a positive float value.");
+            std::process::exit(1);
+        }
+        if correlation_length <= 0.0 {
+            eprintln!("Error: correlation_length must be a positive, non-zero, float value.");
+            std::process::exit(1);
+        }
+
+        Self {
+            rms_height,
+            correlation_length,
+            scattering,
+        }
+    } // Initialisation function to define a rough interface after undergoing necessary error checks.
+
+    pub fn scattering(&self) -> Scattering {
+        self.scattering
+    }
+
+    pub fn coherent_fraction(&self, wavenumber: f64, cos_incidence: f64) -> f64 {
+        (-4.0 * (wavenumber * self.rms_height * cos_incidence).powi(2)).exp()
+    } // Fraction of the reflected energy which stays in the specular direction (Kirchhoff/Eckart), with the Rayleigh parameter 2 k h cos(theta).
+
+    pub fn diffuse_directions(&self, incident: [f64;2], normal: [f64;2]) -> Vec<([f64;2], f64)> {
+        let number_of_rays = match self.scattering {
+            Scattering::Specular => return Vec::new(),
+            Scattering::Lambert(number_of_rays) | Scattering::Kirchhoff(number_of_rays) => number_of_rays,
+        };
+        if self.rms_height == 0.0 { return Vec::new() }
+        // A smooth interface reflects all of its energy coherently, and has no slopes to scatter from.
+        let tangent = [-normal[1], normal[0]];
+        let rms_slope = 2.0_f64.sqrt() * self.rms_height / self.correlation_length;
+        // Gaussian correlated surface, used by the Kirchhoff (facet) pattern.
+
+        let mut directions = Vec::with_capacity(number_of_rays);
+        for i in 0..number_of_rays {
+            let angle = -PI / 2.0 + (i as f64 + 0.5) * PI / number_of_rays as f64;
+            let direction = [angle.cos() * normal[0] + angle.sin() * tangent[0], angle.cos() * normal[1] + angle.sin() * tangent[1]];
+
+            let weight = match self.scattering {
+                Scattering::Kirchhoff(_) => {
+                    let bisector = [direction[0] - incident[0], direction[1] - incident[1]];
+                    let cos_tilt = (bisector[0] * normal[0] + bisector[1] * normal[1]) / (bisector[0].powi(2) + bisector[1].powi(2)).sqrt();
+                    let tan_tilt_squared = (1.0 - cos_tilt.powi(2)) / cos_tilt.powi(2);
+                    (-tan_tilt_squared / (2.0 * rms_slope.powi(2))).exp() / cos_tilt.powi(4)
+                } // Energy is scattered by the facets which are tilted to reflect the ray into each direction.
+                _ => angle.cos(),
+            };
+            directions.push((direction, weight));
+        } // Spreads the diffuse rays evenly across the half-space on the incident side of the interface.
+
+        let total_weight: f64 = directions.iter().map(|(_, weight)| weight).sum();
+        if total_weight > 0.0 {
+            for (_, weight) in directions.iter_mut() { *weight /= total_weight }
+        }
+        directions
+    } // Returns each diffuse ray direction with the fraction of the scattered energy it carries. 'normal' must point back towards the incident side.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooth_interfaces_do_not_scatter() {
+        for scattering in [Scattering::Lambert(8), Scattering::Kirchhoff(8)] {
+            let roughness = Roughness::new(0.0, 10.0, scattering);
+            assert_eq!(roughness.coherent_fraction(1.0, 1.0), 1.0);
+            assert!(roughness.diffuse_directions([0.6, 0.8], [0.0, -1.0]).is_empty());
+        }
+    }
+
+    #[test]
+    fn kirchhoff_weights_sum_to_one() {
+        let roughness = Roughness::new(0.5, 10.0, Scattering::Kirchhoff(16));
+        let directions = roughness.diffuse_directions([0.6, 0.8], [0.0, -1.0]);
+        assert_eq!(directions.len(), 16);
+        assert!(directions.iter().all(|(_, weight)| weight.is_finite()));
+        assert!((directions.iter().map(|(_, weight)| weight).sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+}
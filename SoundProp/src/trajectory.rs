@@ -0,0 +1,68 @@
+//                                                  MARK: Trajectory Struct
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trajectory {
+    waypoints: Vec<[f64;2]>,
+    speeds: Vec<f64>,
+    times: Vec<f64>,
+} // A path through a list of waypoints, holding the time each one is reached.
+
+impl Trajectory {
+    pub fn new(waypoints: Vec<[f64;3]>) -> Self {
+        if waypoints.is_empty() {
+            eprintln!("Error: A trajectory must have at least one waypoint.");
+            std::process::exit(1);
+        }
+
+        let mut times = vec![0.0];
+        for leg in waypoints.windows(2) {
+            let length = ((leg[1][0] - leg[0][0]).powi(2) + (leg[1][1] - leg[0][1]).powi(2)).sqrt();
+            if length == 0.0 {
+                times.push(times[times.len() - 1]);
+                continue;
+            } // Repeated waypoints are passed straight through.
+            if leg[0][2].is_nan() || leg[0][2] <= 0.0 {
+                eprintln!("Error: The speed at each waypoint, other than the last, must be a positive, non-zero, float value.");
+                std::process::exit(1);
+            }
+            times.push(times[times.len() - 1] + length / leg[0][2]);
+        }
+
+        Self {
+            waypoints: waypoints.iter().map(|waypoint| [waypoint[0], waypoint[1]]).collect(),
+            speeds: waypoints.iter().map(|waypoint| waypoint[2]).collect(),
+            times,
+        }
+    } // Takes each waypoint as [x, y, speed], where the speed is kept until the next waypoint. It stays at the last waypoint once it is reached.
+
+    pub fn start(&self) -> [f64;2] {
+        self.waypoints[0]
+    }
+
+    fn leg(&self, time: f64) -> Option<usize> {
+        if time < 0.0 { return None }
+        (0..self.waypoints.len() - 1).find(|k| time < self.times[k + 1])
+    } // The index of the waypoint at the start of the leg being travelled at the given time.
+
+    pub fn position(&self, time: f64) -> [f64;2] {
+        match self.leg(time) {
+            Some(k) => {
+                let fraction = (time - self.times[k]) / (self.times[k + 1] - self.times[k]);
+                [self.waypoints[k][0] + fraction * (self.waypoints[k + 1][0] - self.waypoints[k][0]),
+                 self.waypoints[k][1] + fraction * (self.waypoints[k + 1][1] - self.waypoints[k][1])]
+            }
+            None if time < 0.0 => self.waypoints[0],
+            None => self.waypoints[self.waypoints.len() - 1],
+        }
+    }
+
+    pub fn velocity(&self, time: f64) -> [f64;2] {
+        match self.leg(time) {
+            Some(k) => {
+                let delta = [self.waypoints[k + 1][0] - self.waypoints[k][0], self.waypoints[k + 1][1] - self.waypoints[k][1]];
+                let length = (delta[0].powi(2) + delta[1].powi(2)).sqrt();
+                [self.speeds[k] * delta[0] / length, self.speeds[k] * delta[1] / length]
+            }
+            None => [0.0, 0.0],
+        }
+    }
+}
@@ -0,0 +1,235 @@
+const PCM: u16 = 1;
+const IEEE_FLOAT: u16 = 3;
+const EXTENSIBLE: u16 = 0xFFFE;
+const CALIBRATION_KEY: &str = "calibration=";
+
+//                                                  MARK: Sample Format Enum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    Pcm16,
+    Pcm24,
+    Pcm32,
+    Float32,
+    Float64,
+}
+
+impl SampleFormat {
+    fn format_tag(&self) -> u16 {
+        match self {
+            Self::Pcm16 | Self::Pcm24 | Self::Pcm32 => PCM,
+            Self::Float32 | Self::Float64 => IEEE_FLOAT,
+        }
+    }
+
+    fn bits_per_sample(&self) -> u16 {
+        match self {
+            Self::Pcm16 => 16,
+            Self::Pcm24 => 24,
+            Self::Pcm32 | Self::Float32 => 32,
+            Self::Float64 => 64,
+        }
+    }
+
+    fn full_scale(&self) -> f64 {
+        match self {
+            Self::Pcm16 => 32767.0,
+            Self::Pcm24 => 8388607.0,
+            Self::Pcm32 => 2147483647.0,
+            Self::Float32 | Self::Float64 => 1.0,
+        }
+    } // The largest integer each PCM format can hold.
+}
+
+pub fn encode(sample_rate: f64, samples: &[f64], format: SampleFormat) -> Vec<u8> {
+    let calibration = match format {
+        SampleFormat::Float32 | SampleFormat::Float64 => 1.0,
+        _ => samples.iter().fold(0.0, |peak: f64, sample| peak.max(sample.abs())),
+    };
+    let calibration = if calibration > 0.0 { calibration } else { 1.0 };
+    // PCM samples are scaled so the peak fills the full range. Float samples are kept unchanged.
+
+    let bytes_per_sample = (format.bits_per_sample() / 8) as usize;
+    let mut data = Vec::with_capacity(samples.len() * bytes_per_sample);
+    for sample in samples {
+        let value = sample / calibration;
+        match format {
+            SampleFormat::Pcm16 => data.extend_from_slice(&((value * format.full_scale()).round() as i16).to_le_bytes()),
+            SampleFormat::Pcm24 => data.extend_from_slice(&((value * format.full_scale()).round() as i32).to_le_bytes()[0..3]),
+            SampleFormat::Pcm32 => data.extend_from_slice(&((value * format.full_scale()).round() as i32).to_le_bytes()),
+            SampleFormat::Float32 => data.extend_from_slice(&(value as f32).to_le_bytes()),
+            SampleFormat::Float64 => data.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+    if data.len() % 2 == 1 { data.push(0) }
+
+    let mut info = Vec::new();
+    info.extend_from_slice(b"INFO");
+    for (id, text) in [(b"ISFT", format!("SoundProp {}", env!("CARGO_PKG_VERSION"))), (b"ICMT", format!("{}{:e}", CALIBRATION_KEY, calibration))] {
+        let mut text = text.into_bytes();
+        text.push(0);
+        info.extend_from_slice(id);
+        info.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        if text.len() % 2 == 1 { text.push(0) }
+        info.extend_from_slice(&text);
+    } // Null terminated strings, padded to an even length.
+
+    let sample_rate = sample_rate.round() as u32;
+    let block_align = bytes_per_sample as u16;
+    let mut bytes = Vec::with_capacity(60 + info.len() + data.len());
+
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&((4 + 24 + 8 + info.len() + 8 + data.len()) as u32).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&format.format_tag().to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes()); // Bytes per second
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&format.bits_per_sample().to_le_bytes());
+
+    bytes.extend_from_slice(b"LIST");
+    bytes.extend_from_slice(&(info.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&info);
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&((samples.len() * bytes_per_sample) as u32).to_le_bytes());
+    bytes.extend_from_slice(&data);
+    bytes
+} // Encodes a mono WAV file. The value of a full scale sample is kept in the comment of a LIST INFO chunk, so the file can be read back in its original units.
+
+pub fn decode(bytes: &[u8]) -> Result<(f64, Vec<f64>), &'static str> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("Not a RIFF WAVE file.");
+    }
+
+    let mut format: Option<(u16, u16, u32, u16)> = None;
+    let mut calibration: Option<f64> = None;
+    let mut data: Option<&[u8]> = None;
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let id = &bytes[position..position + 4];
+        let length = u32::from_le_bytes(bytes[position + 4..position + 8].try_into().unwrap()) as usize;
+        let start = position + 8;
+        let end = (start + length).min(bytes.len());
+        let chunk = &bytes[start..end];
+
+        if id == b"fmt " {
+            if chunk.len() < 16 { return Err("The format chunk is too short.") }
+            let mut format_tag = u16::from_le_bytes([chunk[0], chunk[1]]);
+            if format_tag == EXTENSIBLE {
+                if chunk.len() < 26 { return Err("The extensible format chunk is too short.") }
+                format_tag = u16::from_le_bytes([chunk[24], chunk[25]]);
+            } // The sub-format GUID starts with the ordinary format tag.
+            format = Some((
+                format_tag,
+                u16::from_le_bytes([chunk[2], chunk[3]]),
+                u32::from_le_bytes(chunk[4..8].try_into().unwrap()),
+                u16::from_le_bytes([chunk[14], chunk[15]]),
+            ));
+        } else if id == b"LIST" && chunk.len() >= 4 && &chunk[0..4] == b"INFO" {
+            calibration = read_calibration(&chunk[4..]);
+        } else if id == b"data" {
+            data = Some(chunk);
+        }
+        position = start + length + length % 2;
+        // Chunks are padded to an even length.
+    }
+
+    let (format_tag, channels, sample_rate, bits_per_sample) = format.ok_or("The file has no format chunk.")?;
+    let data = data.ok_or("The file has no data chunk.")?;
+    let samples: Vec<f64> = match (format_tag, bits_per_sample) {
+        (PCM, 8) => data.iter().map(|b| (*b as f64 - 128.0) / 128.0).collect(),
+        (PCM, 16) => data.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]]) as f64 / 32768.0).collect(),
+        (PCM, 24) => data.chunks_exact(3).map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f64 / 2147483648.0).collect(),
+        (PCM, 32) => data.chunks_exact(4).map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2147483648.0).collect(),
+        (IEEE_FLOAT, 32) => data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64).collect(),
+        (IEEE_FLOAT, 64) => data.chunks_exact(8).map(|b| f64::from_le_bytes(b.try_into().unwrap())).collect(),
+        _ => return Err("Only 8, 16, 24 and 32-bit PCM and 32 and 64-bit float WAV files are supported."),
+    }; // 24-bit samples are shifted into the top of an i32 to keep their sign.
+
+    let full_scale = match (format_tag, bits_per_sample) {
+        (PCM, 8) | (IEEE_FLOAT, _) => 1.0,
+        (_, bits) => 2.0_f64.powi(bits as i32 - 1) / (2.0_f64.powi(bits as i32 - 1) - 1.0),
+    }; // Files written by encode use the largest integer, rather than the magnitude of the smallest, as full scale.
+    let scale = match calibration {
+        Some(calibration) => calibration * full_scale,
+        None => 1.0,
+    };
+
+    Ok((sample_rate as f64, samples.into_iter().step_by(channels.max(1) as usize).map(|sample| sample * scale).collect()))
+    // Only the first channel is kept.
+} // Decodes a WAV file into its sample rate and samples. PCM samples are scaled to +/- 1.0, unless the file has a calibration.
+
+fn read_calibration(info: &[u8]) -> Option<f64> {
+    let mut position = 0;
+    while position + 8 <= info.len() {
+        let length = u32::from_le_bytes(info[position + 4..position + 8].try_into().unwrap()) as usize;
+        let end = (position + 8 + length).min(info.len());
+        if &info[position..position + 4] == b"ICMT" {
+            let text = String::from_utf8_lossy(&info[position + 8..end]);
+            if let Some(value) = text.trim_end_matches('\0').strip_prefix(CALIBRATION_KEY) {
+                return value.trim().parse().ok();
+            }
+        }
+        position = end + length % 2;
+    }
+    None
+} // Finds the value of a full scale sample in the comment of a LIST INFO chunk.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [SampleFormat; 5] = [SampleFormat::Pcm16, SampleFormat::Pcm24, SampleFormat::Pcm32, SampleFormat::Float32, SampleFormat::Float64];
+
+    fn signal() -> Vec<f64> {
+        (0..101).map(|i| 250.0 * (0.3 * i as f64).sin() - 0.5).collect()
+    } // An odd number of samples with both signs, so the 24-bit data chunk needs padding.
+
+    #[test]
+    fn round_trips_keep_the_sample_rate_and_calibrated_amplitudes() {
+        let samples = signal();
+        let peak = samples.iter().fold(0.0, |peak: f64, sample| peak.max(sample.abs()));
+        for format in FORMATS {
+            let (sample_rate, decoded) = decode(&encode(44100.0, &samples, format)).unwrap();
+            assert_eq!(sample_rate, 44100.0, "{:?}", format);
+            assert_eq!(decoded.len(), samples.len(), "{:?}", format);
+
+            let tolerance = match format {
+                SampleFormat::Float32 => peak * f32::EPSILON as f64,
+                SampleFormat::Float64 => 0.0,
+                _ => 0.5 * peak / format.full_scale() * (1.0 + 1e-9),
+            }; // PCM samples are rounded to the nearest step of the calibration over the largest integer.
+            for (sample, value) in samples.iter().zip(&decoded) {
+                assert!((sample - value).abs() <= tolerance, "{:?}: {} became {}", format, sample, value);
+            }
+        }
+    }
+
+    #[test]
+    fn pcm_peaks_decode_to_the_calibration() {
+        for format in [SampleFormat::Pcm16, SampleFormat::Pcm24, SampleFormat::Pcm32] {
+            let (_, decoded) = decode(&encode(8000.0, &[-3.0, 3.0, 0.0], format)).unwrap();
+            assert_eq!(decoded[0], -3.0, "{:?}", format);
+            assert_eq!(decoded[1], 3.0, "{:?}", format);
+            assert_eq!(decoded[2], 0.0, "{:?}", format);
+        }
+    } // Full scale is the largest integer, so the peaks come back exactly, with negative 24-bit samples keeping their sign.
+
+    #[test]
+    fn silence_is_not_scaled() {
+        for format in FORMATS {
+            let (_, decoded) = decode(&encode(8000.0, &[0.0; 4], format)).unwrap();
+            assert_eq!(decoded, vec![0.0; 4], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn rejects_files_which_are_not_wav() {
+        assert!(decode(b"RIFF\0\0\0\0AVI ").is_err());
+        assert!(decode(&encode(8000.0, &[1.0], SampleFormat::Pcm16)[..36]).is_err());
+    }
+}
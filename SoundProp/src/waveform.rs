@@ -0,0 +1,102 @@
+use std::{f64::consts::PI, fs};
+
+use crate::wav;
+
+const HILBERT_HALF_LENGTH: usize = 32;
+
+//                                                  MARK: Waveform Enum
+#[derive(Clone, Debug, PartialEq)]
+pub enum Waveform {
+    Ricker { peak_frequency: f64 },
+    Chirp { start_frequency: f64, end_frequency: f64, duration: f64 },
+    ToneBurst { frequency: f64, cycles: f64 },
+    Samples { sample_rate: f64, samples: Vec<f64> },
+} // The pressure signal emitted by a source, starting at its emission time.
+
+impl Waveform {
+    pub fn from_wav(file_name: &str) -> Self {
+        let bytes = match fs::read(file_name) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                eprintln!("Error reading file {}: {}", file_name, err);
+                std::process::exit(1);
+            }
+        };
+        match wav::decode(&bytes) {
+            Ok((sample_rate, samples)) => Self::Samples { sample_rate, samples },
+            Err(err) => {
+                eprintln!("Error reading file {}: {}", file_name, err);
+                std::process::exit(1);
+            }
+        }
+    } // Uses a recorded signal, such as a ship or an airgun, as the waveform.
+
+    pub fn samples(&self, sample_rate: f64) -> Vec<f64> {
+        if sample_rate <= 0.0 {
+            eprintln!("Error: sample_rate must be a positive, non-zero, float value.");
+            std::process::exit(1);
+        }
+
+        match self {
+            Self::Ricker { peak_frequency } => {
+                check_positive(&[*peak_frequency]);
+                let delay = 1.5 / peak_frequency;
+                sample_times(2.0 * delay, sample_rate).map(|t| {
+                    let argument = (PI * peak_frequency * (t - delay)).powi(2);
+                    (1.0 - 2.0 * argument) * (-argument).exp()
+                }).collect()
+            } // Delayed so that the wavelet has decayed to nothing at its start.
+            Self::Chirp { start_frequency, end_frequency, duration } => {
+                check_positive(&[*start_frequency, *end_frequency, *duration]);
+                let sweep_rate = (end_frequency - start_frequency) / duration;
+                sample_times(*duration, sample_rate)
+                    .map(|t| (2.0 * PI * (start_frequency * t + 0.5 * sweep_rate * t * t)).sin())
+                    .collect()
+            } // Linear frequency modulated sweep.
+            Self::ToneBurst { frequency, cycles } => {
+                check_positive(&[*frequency, *cycles]);
+                let duration = cycles / frequency;
+                sample_times(duration, sample_rate)
+                    .map(|t| (2.0 * PI * frequency * t).sin() * (PI * t / duration).sin().powi(2))
+                    .collect()
+            } // A Hann windowed tone, which avoids the clicks at the ends of a gated sine.
+            Self::Samples { sample_rate: original_rate, samples } => {
+                check_positive(&[*original_rate]);
+                if samples.is_empty() { return Vec::new() }
+                let duration = (samples.len() - 1) as f64 / original_rate;
+                sample_times(duration, sample_rate).map(|t| {
+                    let position = t * original_rate;
+                    let index = (position.floor() as usize).min(samples.len() - 1);
+                    let next = (index + 1).min(samples.len() - 1);
+                    samples[index] + (position - index as f64) * (samples[next] - samples[index])
+                }).collect()
+            } // Resampled with linear interpolation.
+        }
+    } // Samples the waveform at the given rate, starting from t = 0.0.
+}
+
+fn sample_times(duration: f64, sample_rate: f64) -> impl Iterator<Item = f64> {
+    let number_of_samples = (duration * sample_rate).floor() as usize + 1;
+    (0..number_of_samples).map(move |n| n as f64 / sample_rate)
+}
+
+fn check_positive(values: &[f64]) {
+    if values.iter().any(|value| value.is_nan() || *value <= 0.0) {
+        eprintln!("Error: The frequencies, durations and sample rate of a waveform must be positive, non-zero, float values.");
+        std::process::exit(1);
+    }
+}
+
+pub fn hilbert(signal: &[f64]) -> Vec<f64> {
+    let mut quadrature = vec![0.0; signal.len()];
+    for n in 0..signal.len() {
+        for k in (1..=HILBERT_HALF_LENGTH).step_by(2) {
+            let window = 0.54 + 0.46 * (PI * k as f64 / (HILBERT_HALF_LENGTH + 1) as f64).cos();
+            let coefficient = 2.0 / (PI * k as f64) * window;
+            let before = if n >= k { signal[n - k] } else { 0.0 };
+            let after = if n + k < signal.len() { signal[n + k] } else { 0.0 };
+            quadrature[n] += coefficient * (before - after);
+        }
+    }
+    quadrature
+} // Hamming windowed FIR Hilbert transformer. Shifts every frequency in the signal by -π/2, so arrivals can be given any phase.